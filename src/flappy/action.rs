@@ -0,0 +1,106 @@
+use rand::Rng;
+
+// Outputs at least this high flap with the continuous head
+const DEAD_ZONE: f32 = 0.5;
+
+/**
+ * How the outputs of a brain become a flap. The size of the
+ * brain's output layer follows from it
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ActionHead {
+    // Two outputs, flap when the second is higher, what birds always had
+    #[default]
+    Argmax,
+    // One output, flap when it's above the threshold
+    Threshold(f32),
+    // One output, above the dead zone it flaps as hard as it's high
+    Continuous,
+    // One output, the chance of flapping
+    Stochastic,
+}
+
+impl ActionHead {
+    pub fn parse(name: &str) -> Result<ActionHead, String> {
+        match name {
+            "argmax" => Ok(ActionHead::Argmax),
+            "threshold" => Ok(ActionHead::Threshold(0.5)),
+            "continuous" => Ok(ActionHead::Continuous),
+            "stochastic" => Ok(ActionHead::Stochastic),
+            _ => match name.strip_prefix("threshold:").map(|t| t.parse()) {
+                Some(Ok(threshold)) => Ok(ActionHead::Threshold(threshold)),
+                Some(Err(_)) => Err(format!("{} needs a number", name)),
+                None => Err(format!(
+                    "Unknown action head {}, use argmax, threshold[:t], continuous or stochastic",
+                    name
+                )),
+            },
+        }
+    }
+
+    // Outputs the brain needs
+    pub fn outputs(self) -> i32 {
+        match self {
+            ActionHead::Argmax => 2,
+            _ => 1,
+        }
+    }
+
+    // Chance of a flap with these outputs
+    pub fn chance(self, outputs: &[f32]) -> f32 {
+        let flaps = match self {
+            ActionHead::Argmax => outputs[1] > outputs[0],
+            ActionHead::Threshold(threshold) => outputs[0] > threshold,
+            ActionHead::Continuous => outputs[0] >= DEAD_ZONE,
+            ActionHead::Stochastic => return outputs[0].clamp(0.0, 1.0),
+        };
+        if flaps {
+            1.0
+        } else {
+            0.0
+        }
+    }
+
+    // How hard to flap, from 0 to 1, if at all
+    pub fn act<R: Rng>(self, outputs: &[f32], rng: &mut R) -> Option<f32> {
+        let chance = self.chance(outputs);
+        let flaps = match self {
+            ActionHead::Stochastic => rng.gen_range(0.0, 1.0) < chance,
+            _ => chance > 0.5,
+        };
+        match self {
+            _ if !flaps => None,
+            ActionHead::Continuous => Some((outputs[0] - DEAD_ZONE) / (1.0 - DEAD_ZONE)),
+            _ => Some(1.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn heads_turn_outputs_into_flaps() {
+        let mut rng = StdRng::seed_from_u64(0);
+        assert_eq!(ActionHead::Argmax.act(&[0.2, 0.7], &mut rng), Some(1.0));
+        assert_eq!(ActionHead::Argmax.act(&[0.7, 0.2], &mut rng), None);
+        let threshold = ActionHead::parse("threshold:0.8").unwrap();
+        assert_eq!(threshold.outputs(), 1);
+        assert_eq!(threshold.act(&[0.7], &mut rng), None);
+        assert_eq!(ActionHead::Continuous.act(&[0.75], &mut rng), Some(0.5));
+        assert_eq!(ActionHead::Continuous.act(&[0.25], &mut rng), None);
+    }
+
+    #[test]
+    fn stochastic_head_flaps_as_often_as_it_says() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let flaps = (0..10000)
+            .filter(|_| ActionHead::Stochastic.act(&[0.3], &mut rng).is_some())
+            .count();
+        assert!((2700..3300).contains(&flaps));
+        assert_eq!(ActionHead::Stochastic.act(&[0.0], &mut rng), None);
+    }
+}
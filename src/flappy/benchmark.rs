@@ -0,0 +1,156 @@
+use crate::flappy::bird::Bird;
+use crate::flappy::episode::{self, Aggregate, Episode, Outcome, MAX_FRAMES};
+use crate::flappy::generator::PipeGenerator;
+use crate::flappy::pipe::PipeConfig;
+use std::cmp::Reverse;
+
+// The benchmark courses, seeds 1 to 100. Never change them,
+// scores are only comparable as long as the courses stay the same
+pub const COURSES: u64 = 100;
+const FIRST_SEED: u64 = 1;
+// Columns of the survival histogram
+const BUCKETS: u32 = 10;
+const BAR_WIDTH: usize = 40;
+
+/**
+ * How one brain did on every benchmark course, gives a
+ * score that can be compared between brains and commits
+ */
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub episodes: Vec<Episode>,
+    pub max_frames: u32,
+}
+
+impl Report {
+    // Flies bird, with its brain, sensors and physics, through every course
+    pub fn run(bird: &Bird) -> Result<Report, String> {
+        bird.check_brain()?;
+        // Always the classic pipes, whatever the brain was trained on
+        let (width, height) = (bird.width(), bird.height());
+        let course = PipeGenerator::new(width, height, FIRST_SEED, PipeConfig::default());
+        let episodes = (FIRST_SEED..FIRST_SEED + COURSES)
+            .map(|seed| episode::run(bird, &course, seed, MAX_FRAMES))
+            .collect();
+        Ok(Report {
+            episodes,
+            max_frames: MAX_FRAMES,
+        })
+    }
+
+    // Mean, min and max pipes passed
+    pub fn pipes(&self) -> (f32, u32, u32) {
+        let pipes: Vec<u32> = self.episodes.iter().map(|e| e.pipes_passed).collect();
+        let mean = pipes.iter().sum::<u32>() as f32 / pipes.len().max(1) as f32;
+        let min = pipes.iter().cloned().min().unwrap_or(0);
+        let max = pipes.iter().cloned().max().unwrap_or(0);
+        (mean, min, max)
+    }
+
+    // Frames survived at the given quantile
+    pub fn survival(&self, q: f32) -> f32 {
+        let frames: Vec<f32> = self.episodes.iter().map(|e| e.frames as f32).collect();
+        Aggregate::Quantile(q).apply(&frames)
+    }
+
+    // Episodes per survival time bucket, the last one ends at max_frames
+    pub fn histogram(&self) -> Vec<usize> {
+        let mut buckets = vec![0; BUCKETS as usize];
+        for episode in self.episodes.iter() {
+            let i = (episode.frames * BUCKETS / self.max_frames.max(1)).min(BUCKETS - 1);
+            buckets[i as usize] += 1;
+        }
+        buckets
+    }
+
+    // How many episodes ended each way, most common first
+    pub fn outcomes(&self) -> Vec<(Outcome, usize)> {
+        let all = [
+            Outcome::TopPipe,
+            Outcome::BottomPipe,
+            Outcome::Ceiling,
+            Outcome::Floor,
+            Outcome::Survived,
+        ];
+        let mut counts: Vec<(Outcome, usize)> = all
+            .iter()
+            .map(|&o| (o, self.episodes.iter().filter(|e| e.outcome == o).count()))
+            .collect();
+        counts.sort_by_key(|&(_, count)| Reverse(count));
+        counts
+    }
+
+    pub fn summary(&self) -> String {
+        let n = self.episodes.len().max(1);
+        let (mean, min, max) = self.pipes();
+        let mut out = format!(
+            "{} courses, at most {} frames each\n\nPipes passed: mean {:.1}, min {}, max {}\n\n",
+            self.episodes.len(),
+            self.max_frames,
+            mean,
+            min,
+            max
+        );
+        out.push_str(&format!(
+            "Frames survived: min {} p10 {} p25 {} median {} p75 {} p90 {} max {}\n",
+            self.survival(0.0),
+            self.survival(0.1),
+            self.survival(0.25),
+            self.survival(0.5),
+            self.survival(0.75),
+            self.survival(0.9),
+            self.survival(1.0)
+        ));
+        let histogram = self.histogram();
+        let highest = histogram.iter().cloned().max().unwrap_or(0).max(1);
+        let step = self.max_frames / BUCKETS;
+        for (i, &count) in histogram.iter().enumerate() {
+            let from = i as u32 * step;
+            let bar = "#".repeat(count * BAR_WIDTH / highest);
+            let line = format!("  {:>5}-{:<5} {:>3} {}", from, from + step, count, bar);
+            out.push_str(line.trim_end());
+            out.push('\n');
+        }
+        out.push_str("\nEnded by:\n");
+        for (outcome, count) in self.outcomes() {
+            out.push_str(&format!(
+                "  {:<12} {:>3} ({:.0}%)\n",
+                outcome.name(),
+                count,
+                count as f32 * 100.0 / n as f32
+            ));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn episode(frames: u32, outcome: Outcome) -> Episode {
+        Episode {
+            seed: 0,
+            frames,
+            pipes_passed: frames / 100,
+            outcome,
+        }
+    }
+
+    #[test]
+    fn summarizes_episodes() {
+        let report = Report {
+            episodes: vec![
+                episode(50, Outcome::Ceiling),
+                episode(450, Outcome::TopPipe),
+                episode(900, Outcome::TopPipe),
+                episode(1000, Outcome::Survived),
+            ],
+            max_frames: 1000,
+        };
+        assert_eq!(report.pipes(), (5.75, 0, 10));
+        assert_eq!(report.histogram(), vec![1, 0, 0, 0, 1, 0, 0, 0, 0, 2]);
+        assert_eq!(report.outcomes()[0], (Outcome::TopPipe, 2));
+        assert!(report.summary().contains("top pipe       2 (50%)"));
+    }
+}
@@ -3,40 +3,77 @@ use rand::distributions::Standard;
 use rand::prelude::*;
 use rand::Rng;
 
-use sdl2::rect::Point;
-use sdl2::render::Canvas;
-use sdl2::video::Window;
+use crate::flappy::action::ActionHead;
+use crate::flappy::normalize::Normalizer;
+use crate::flappy::physics::PhysicsConfig;
+use crate::flappy::pipe::{Pipe, PipeConfig};
+use crate::flappy::sensors::{Limits, SensorSet};
+use crate::renderer::target::DrawTarget;
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU64, Ordering};
 
-use crate::flappy::pipe::Pipe;
-use crate::renderer::renderer::Renderer;
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+// Chance of every weight to change when a bird is copied
+pub const MUTATION_RATE: f32 = 0.1;
+// Standard deviation of the change
+pub const MUTATION_SIZE: f32 = 0.5;
 
 fn random_gaussian() -> f32 {
     StdRng::from_entropy().sample(Standard)
 }
-fn range_map(n: f32, start1: f32, stop1: f32, start2: f32, stop2: f32) -> f32 {
-    ((n - start1) / (stop1 - start1)) * (stop2 - start2) + start2
-}
 
 #[derive(Debug, Clone)]
 pub struct Bird {
     pub x: f32,
     pub y: f32,
+    // Height before the last update, for drawing in between steps
+    pub prev_y: f32,
     pub r: f32,
-    pub gravity: f32,
-    pub lift: i32,
+    pub physics: PhysicsConfig,
     pub velocity: f32,
     pub brain: NeuralNetwork,
     pub score: u64,
     pub fitness: f32,
+    // Flap decision of every frame lived, for recordings
+    pub flaps: Vec<bool>,
+    // How hard every one of those flaps was
+    pub strengths: Vec<f32>,
+    // Unique for every bird ever made
+    pub id: u64,
+    // Birds this one was bred from, none for the first generation
+    pub parents: Vec<u64>,
+    // Generation the bird was born in
+    pub born: u32,
+    // How many weights changed when it was bred
+    pub mutations: u32,
+    // Founder of the family this bird descends from
+    pub lineage: u32,
+    // Fitness of the bird this one was copied from
+    pub parent_fitness: f32,
+    // What the brain gets to know, one input per reading
+    pub sensors: SensorSet,
+    // Turns the readings into inputs, and keeps statistics of them
+    pub normalizer: Normalizer,
+    // Turns the outputs of the brain into a flap
+    pub head: ActionHead,
+    // Pipes it's going to fly through, only for the ranges of its sensors
+    pipes: PipeConfig,
+    // For heads that flap by chance, boxed since it's big
+    rng: Box<StdRng>,
+
+    flapped: bool,
+    // Frames until flapping does anything again
+    cooldown: u32,
 
     width: u32,
     height: u32,
 }
 
-fn mutate(x: f32) -> f32 {
+fn mutate(x: f32, rate: f32, size: f32) -> f32 {
     let mut res_x = x;
-    if rand::thread_rng().gen_range(0.0, 1.0) < 0.1 {
-        let offset = random_gaussian() * 0.5;
+    if rand::thread_rng().gen_range(0.0, 1.0) < rate {
+        let offset = random_gaussian() * size;
         let new_x = x + offset;
         res_x = new_x;
     }
@@ -45,14 +82,15 @@ fn mutate(x: f32) -> f32 {
 
 impl Bird {
     pub fn new(width: u32, height: u32, brain: NeuralNetwork) -> Bird {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
         Bird {
             // position and size of bird
             x: 64.0,
             y: height as f32 / 2.0,
-            r: 12.0,
+            prev_y: height as f32 / 2.0,
+            r: PhysicsConfig::default().radius,
             // Gravity, lift and velocity
-            gravity: 0.8,
-            lift: -12,
+            physics: PhysicsConfig::default(),
             velocity: 0.0,
 
             brain: brain,
@@ -60,72 +98,213 @@ impl Bird {
             score: 0,
             // Fitness is normalized version of score
             fitness: 0.0,
+            flaps: Vec::new(),
+            strengths: Vec::new(),
+            id,
+            parents: Vec::new(),
+            born: 0,
+            mutations: 0,
+            lineage: 0,
+            parent_fitness: 0.0,
+            sensors: SensorSet::default(),
+            normalizer: Normalizer::new(
+                &SensorSet::default(),
+                &Limits::new(
+                    width,
+                    height,
+                    &PhysicsConfig::default(),
+                    &PipeConfig::default(),
+                ),
+            ),
+            head: ActionHead::default(),
+            pipes: PipeConfig::default(),
+            // Cheap next to asking the OS for every bird, seed_policy
+            // is what makes episodes repeatable
+            rng: Box::new(StdRng::seed_from_u64(id)),
+            flapped: false,
+            cooldown: 0,
             height,
             width,
         }
     }
 
-    pub fn copy(&self) -> Bird {
+    // A child where every weight changes by about size with chance rate
+    pub fn mutated(&self, rate: f32, size: f32) -> Bird {
         let mut new_nn = self.brain.copy();
-        new_nn.mutate(mutate);
-        Bird::new(self.width, self.height, new_nn)
+        let mutations = Cell::new(0);
+        new_nn.mutate(|x| {
+            let y = mutate(x, rate, size);
+            if y != x {
+                mutations.set(mutations.get() + 1);
+            }
+            y
+        });
+        let mut child = Bird::new(self.width, self.height, new_nn);
+        child.parents = vec![self.id];
+        child.born = self.born + 1;
+        child.mutations = mutations.get();
+        child.lineage = self.lineage;
+        child.parent_fitness = self.fitness;
+        child.sensors = self.sensors.clone();
+        child.pipes = self.pipes;
+        child.normalizer = self.normalizer.fresh();
+        child.set_physics(self.physics);
+        child.head = self.head;
+        child
+    }
+
+    // The same brain, senses and physics back at the start, with a new id
+    pub fn reborn(&self) -> Bird {
+        let mut bird = Bird::new(self.width, self.height, self.brain.clone());
+        bird.sensors = self.sensors.clone();
+        bird.pipes = self.pipes;
+        bird.normalizer = self.normalizer.fresh();
+        bird.set_physics(self.physics);
+        bird.head = self.head;
+        bird
+    }
+
+    // Back where it started for another run, with the same brain and family
+    pub fn restart(&mut self) {
+        self.y = self.height as f32 / 2.0;
+        self.prev_y = self.y;
+        self.velocity = 0.0;
+        self.score = 0;
+        self.flaps.clear();
+        self.strengths.clear();
+        self.flapped = false;
+        self.cooldown = 0;
+    }
+
+    // Flap by chance the same way every time, for repeatable episodes
+    pub fn seed_policy(&mut self, seed: u64) {
+        *self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    // Whether the brain takes what the sensors read and gives what the action head needs
+    pub fn check_brain(&self) -> Result<(), String> {
+        let (brain, sensors) = (&self.brain, &self.sensors);
+        let outputs = self.head.outputs();
+        if brain.input_nodes as usize != sensors.size() || brain.output_nodes != outputs {
+            return Err(format!(
+                "These sensors and action head need a brain with {} inputs and {} outputs, not {} and {}",
+                sensors.size(),
+                outputs,
+                brain.input_nodes,
+                brain.output_nodes
+            ));
+        }
+        Ok(())
+    }
+
+    // Senses the world through sensors from now on
+    pub fn set_sensors(&mut self, sensors: SensorSet) {
+        self.normalizer = Normalizer::new(&sensors, &self.limits());
+        self.sensors = sensors;
+    }
+
+    pub fn set_physics(&mut self, physics: PhysicsConfig) {
+        self.r = physics.radius;
+        self.physics = physics;
+        self.normalizer.set_limits(&self.sensors, &self.limits());
+    }
+
+    // Slower pipes take longer to reach it, which its sensors need to know
+    pub fn set_pipe_config(&mut self, config: PipeConfig) {
+        self.pipes = config;
+        self.normalizer.set_limits(&self.sensors, &self.limits());
+    }
+
+    // How far its readings can go
+    pub fn limits(&self) -> Limits {
+        Limits::new(self.width, self.height, &self.physics, &self.pipes)
+    }
+
+    // Where to draw the bird, alpha of the way into the next step
+    pub fn center(&self, alpha: f32) -> (f32, f32) {
+        (self.x, self.prev_y + (self.y - self.prev_y) * alpha)
+    }
+
+    // Nose up while rising, diving when falling fast, in degrees
+    pub fn tilt(&self) -> f32 {
+        (self.velocity * 3.0).clamp(-25.0, 90.0)
     }
 
-    pub fn show(&self, canvas: &mut Canvas<Window>) {
-        Renderer::draw_circle(
-            canvas,
-            Point::new(self.x as i32, self.y as i32),
-            self.r as i32,
-        )
-        .unwrap();
+    // Smooth edged circle, for when only a few birds are drawn
+    pub fn show_smooth(&self, canvas: &mut dyn DrawTarget, alpha: f32) {
+        canvas.fill_circle_aa(self.center(alpha), self.r).unwrap();
     }
     pub fn up(&mut self) {
-        self.velocity += self.lift as f32;
+        self.flap(1.0);
     }
-    pub fn bottom_top(&self) -> bool {
-        self.y > self.height as f32 || self.y < 0.0
+
+    // Flap with strength from 0 to 1, unless the last flap was too recent
+    pub fn flap(&mut self, strength: f32) {
+        if self.cooldown > 0 {
+            return;
+        }
+        self.velocity = self.physics.flap(self.velocity, strength);
+        self.cooldown = self.physics.cooldown;
+        self.flapped = true;
+        self.strengths.push(strength);
+    }
+    pub fn hits_ceiling(&self) -> bool {
+        self.y - self.r < 0.0
+    }
+    pub fn hits_floor(&self) -> bool {
+        self.y + self.r > self.height as f32
     }
 
     pub fn update(&mut self) {
-        self.velocity += self.gravity;
+        self.prev_y = self.y;
+        self.velocity = self.physics.fall(self.velocity);
         self.y += self.velocity;
+        self.cooldown = self.cooldown.saturating_sub(1);
         self.score += 1;
+        self.flaps.push(self.flapped);
+        self.flapped = false;
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    // The inputs of the neural network, from the bird's sensors
+    pub fn inputs(&self, pipes: &[Pipe]) -> Option<Vec<f32>> {
+        let raw = self.sensors.read(self, pipes)?;
+        Some(self.normalizer.normalize(&raw))
     }
 
     /**
      * This is the key function that decides
-     * if it should jump or not jump
+     * if it should jump or not jump. Returns the inputs
+     * it decided on and whether it wanted to flap
      */
-    pub fn think(&mut self, pipes: &Vec<Pipe>) {
-        // First find the closest pipe
-        let mut closest = None;
-        let mut record = f32::INFINITY;
-        for i in 0..pipes.len() {
-            let diff = pipes[i].x - self.x;
-            if diff > 0.0 && diff < record {
-                record = diff;
-                closest = Some(i);
-            }
+    pub fn think(&mut self, pipes: &[Pipe]) -> Option<(Vec<f32>, bool)> {
+        let raw = self.sensors.read(self, pipes)?;
+        self.normalizer.observe(&raw);
+        let inputs = self.normalizer.normalize(&raw);
+        let action = self.act(&inputs);
+        if let Some(strength) = action {
+            self.flap(strength);
         }
+        Some((inputs, action.is_some()))
+    }
 
-        if closest.is_some() {
-            let pipe_i = closest.unwrap();
-            // Now create the inputs to the neural network
-            let mut inputs: [f32; 5] = [0.0; 5];
-            let pipe = &pipes[pipe_i];
-            // x position of closest pipe
-            inputs[0] = range_map(pipe.x, self.x, self.width as f32, 0.0, 1.0);
-            inputs[1] = range_map(pipe.top, 0.0, self.height as f32, 0.0, 1.0);
-            inputs[2] = range_map(pipe.bottom, 0.0, self.height as f32, 0.0, 1.0);
-            inputs[3] = range_map(self.y, 0.0, self.height as f32, 0.0, 1.0);
-            inputs[4] = range_map(self.velocity, -5.0, 5.0, 0.0, 1.0);
-
-            // Get the outputs from the network
-            let action = self.brain.predict(&inputs).unwrap();
-            // Decide to jump or not
-            if action[1] > action[0] {
-                self.up();
-            }
-        };
+    // How hard the brain wants to flap with these inputs, if at all
+    pub fn act(&mut self, inputs: &[f32]) -> Option<f32> {
+        let outputs = self.brain.predict(inputs).unwrap();
+        self.head.act(&outputs, &mut *self.rng)
+    }
+
+    // Would the brain flap with these inputs, or most likely flap?
+    pub fn decide(&self, inputs: &[f32]) -> bool {
+        // Get the outputs from the network
+        let action = self.brain.predict(inputs).unwrap();
+        self.head.chance(&action) > 0.5
     }
 }
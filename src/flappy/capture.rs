@@ -0,0 +1,89 @@
+use crate::flappy::recording::{Recording, Replay};
+use crate::flappy::theme::Theme;
+use crate::renderer::framebuffer::Framebuffer;
+use crate::renderer::gif::GifWriter;
+use crate::renderer::png;
+use crate::renderer::svg::SvgWriter;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+// Milliseconds between two simulation frames
+const FRAME_MS: u32 = 33;
+
+/**
+ * Renders a recorded run offscreen and writes it out. The format
+ * follows the extension of out: .png is a snapshot of a single
+ * frame (the last one unless given), .svg the same frame with
+ * shapes kept as vectors, .gif an animation of every
+ * `every`th frame and .rgba the raw frames one after the other,
+ * ready to be encoded by ffmpeg
+ */
+pub fn capture(
+    recording: Recording,
+    theme: &Theme,
+    out: &str,
+    frame: Option<u32>,
+    every: u32,
+) -> Result<String, String> {
+    let (width, height) = (recording.width, recording.height);
+    let mut replay = Replay::new(recording);
+    let mut buffer = Framebuffer::new(width, height);
+    let every = every.max(1);
+
+    if out.ends_with(".png") {
+        while replay.frame() < frame.unwrap_or(u32::MAX) && replay.step() {}
+        replay.draw(&mut buffer, theme, 1.0)?;
+        png::save(out, width, height, buffer.pixels())?;
+        Ok(format!("Saved frame {} to {}", replay.frame(), out))
+    } else if out.ends_with(".svg") {
+        while replay.frame() < frame.unwrap_or(u32::MAX) && replay.step() {}
+        let mut svg = SvgWriter::new(width, height);
+        replay.draw(&mut svg, theme, 1.0)?;
+        svg.save(out)?;
+        Ok(format!("Saved frame {} to {}", replay.frame(), out))
+    } else if out.ends_with(".gif") {
+        let mut gif = GifWriter::new(width, height, (FRAME_MS * every / 10) as u16)?;
+        let mut frames = 0;
+        loop {
+            if replay.frame() % every == 0 {
+                replay.draw(&mut buffer, theme, 1.0)?;
+                gif.add_frame(buffer.pixels());
+                frames += 1;
+            }
+            if !replay.step() {
+                break;
+            }
+        }
+        gif.save(out)?;
+        Ok(format!("Saved {} frames to {}", frames, out))
+    } else if out.ends_with(".rgba") {
+        let file = File::create(out).map_err(|e| format!("Unable to write {}: {}", out, e))?;
+        let mut writer = BufWriter::new(file);
+        let mut frames = 0;
+        loop {
+            replay.draw(&mut buffer, theme, 1.0)?;
+            writer
+                .write_all(buffer.pixels())
+                .map_err(|e| e.to_string())?;
+            frames += 1;
+            if !replay.step() {
+                break;
+            }
+        }
+        writer.flush().map_err(|e| e.to_string())?;
+        Ok(format!(
+            "Saved {} frames to {}, encode them with:\n  ffmpeg -f rawvideo -pixel_format rgba -video_size {}x{} -framerate {} -i {} run.mp4",
+            frames,
+            out,
+            width,
+            height,
+            1000 / FRAME_MS,
+            out
+        ))
+    } else {
+        Err(format!(
+            "Unknown capture format for {}, use .png, .svg, .gif or .rgba",
+            out
+        ))
+    }
+}
@@ -0,0 +1,88 @@
+use crate::flappy::bird::Bird;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+// Situations every brain is asked about to compare behaviour
+const PROBES: usize = 64;
+const PROBE_SEED: u64 = 7;
+
+/**
+ * Mean euclidean distance between the weights of every pair of
+ * birds. Goes to 0 when the whole population has the same brain
+ */
+pub fn genotype(birds: &[Bird]) -> f32 {
+    if birds.len() < 2 {
+        return 0.0;
+    }
+    let genomes: Vec<Vec<f32>> = birds.iter().map(|b| b.brain.genome()).collect();
+    let mut total = 0.0;
+    for (i, a) in genomes.iter().enumerate() {
+        for b in genomes[i + 1..].iter() {
+            let squared: f32 = a.iter().zip(b.iter()).map(|(x, y)| (x - y) * (x - y)).sum();
+            total += squared.sqrt();
+        }
+    }
+    let pairs = birds.len() * (birds.len() - 1) / 2;
+    total / pairs as f32
+}
+
+/**
+ * How often two birds picked at random make a different call
+ * in the same situation, from 0 when all act alike to 0.5 when
+ * every situation splits the population in half. Brains with
+ * different weights can still fly the same way, this only
+ * counts what shows
+ */
+pub fn behaviour(birds: &[Bird]) -> f32 {
+    if birds.len() < 2 {
+        return 0.0;
+    }
+    // Inputs anywhere they can be, 0..1 or standardized
+    let bounds = birds[0].normalizer.bounds();
+    let mut rng = StdRng::seed_from_u64(PROBE_SEED);
+    let n = birds.len() as f32;
+    let mut total = 0.0;
+    for _ in 0..PROBES {
+        let inputs: Vec<f32> = bounds
+            .iter()
+            .map(|&(min, max)| rng.gen_range(min, max))
+            .collect();
+        let flaps = birds.iter().filter(|b| b.decide(&inputs)).count() as f32;
+        let p = flaps / n;
+        // Chance that one bird flaps and the other doesn't
+        total += 2.0 * p * (1.0 - p);
+    }
+    total / PROBES as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flappy::action::ActionHead;
+    use crate::flappy::testing;
+
+    fn bird(head: ActionHead) -> Bird {
+        let mut bird = testing::bird_with(1);
+        bird.head = head;
+        bird
+    }
+
+    #[test]
+    fn same_brains_have_no_diversity() {
+        let first = bird(ActionHead::Threshold(0.5));
+        let birds = vec![first.clone(), first.clone(), first];
+        assert_eq!(genotype(&birds), 0.0);
+        assert_eq!(behaviour(&birds), 0.0);
+        let other = bird(ActionHead::Threshold(0.5));
+        assert!(genotype(&[birds[0].clone(), other]) > 0.0);
+    }
+
+    #[test]
+    fn split_population_disagrees_half_the_time() {
+        // Sigmoid outputs are always above -1 and below 2
+        let always = bird(ActionHead::Threshold(-1.0));
+        let never = bird(ActionHead::Threshold(2.0));
+        let birds = vec![always.clone(), never.clone(), always, never];
+        assert_eq!(behaviour(&birds), 0.5);
+    }
+}
@@ -0,0 +1,330 @@
+use crate::flappy::bird::Bird;
+use crate::flappy::env::{FlappyEnv, ObservationSpace, Reward, VecEnv};
+use crate::flappy::episode::MAX_FRAMES;
+use crate::flappy::generator::PipeGenerator;
+use crate::flappy::stats::GenerationStats;
+use crate::nn::nn::{Gradients, NeuralNetwork, LINEAR};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::time::Instant;
+
+// Step size, smaller than REINFORCE's since every frame trains
+pub const LEARNING_RATE: f32 = 0.05;
+// Random actions flap this often, a bird flapping half the time hits the ceiling
+const RANDOM_FLAPS: f64 = 0.1;
+// Errors beyond this count linearly in the Huber loss
+const HUBER_DELTA: f32 = 1.0;
+
+// What happened after one decision, None for next once the episode ended
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transition {
+    pub inputs: Vec<f32>,
+    pub flapped: bool,
+    pub reward: f32,
+    pub next: Option<Vec<f32>>,
+}
+
+/**
+ * The last capacity transitions, sampled at random so the
+ * network doesn't only learn from frames right after each other
+ */
+#[derive(Debug, Clone)]
+pub struct ReplayBuffer {
+    capacity: usize,
+    transitions: Vec<Transition>,
+    // Where the next transition goes once the buffer is full
+    oldest: usize,
+}
+
+impl ReplayBuffer {
+    pub fn new(capacity: usize) -> ReplayBuffer {
+        ReplayBuffer {
+            capacity: capacity.max(1),
+            transitions: Vec::new(),
+            oldest: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.transitions.len()
+    }
+
+    pub fn push(&mut self, transition: Transition) {
+        if self.transitions.len() < self.capacity {
+            self.transitions.push(transition);
+        } else {
+            self.transitions[self.oldest] = transition;
+            self.oldest = (self.oldest + 1) % self.capacity;
+        }
+    }
+
+    // n transitions picked at random, the same one can come twice
+    pub fn sample<R: Rng>(&self, n: usize, rng: &mut R) -> Vec<&Transition> {
+        if self.transitions.is_empty() {
+            return Vec::new();
+        }
+        (0..n)
+            .map(|_| &self.transitions[rng.gen_range(0, self.transitions.len())])
+            .collect()
+    }
+}
+
+// Slope of the Huber loss, the squared error near 0 and the absolute error further out
+fn huber_slope(error: f32) -> f32 {
+    error.clamp(-HUBER_DELTA, HUBER_DELTA)
+}
+
+/**
+ * Deep Q-learning: the brain learns the value of gliding and of
+ * flapping in every spot, and the bird does whatever is worth more.
+ * It learns from a replay buffer after every frame, towards values
+ * from a target network that only follows the brain now and then
+ */
+#[derive(Debug, Clone)]
+pub struct Dqn {
+    // Brain, sensors and physics of the bird being trained
+    pub bird: Bird,
+    target: NeuralNetwork,
+    course: PipeGenerator,
+    pub buffer: ReplayBuffer,
+    pub reward: Reward,
+    pub space: ObservationSpace,
+    // Weight of a reward one decision later
+    pub discount: f32,
+    // Chance of a random action, from start to end over decay frames
+    pub epsilon_start: f32,
+    pub epsilon_end: f32,
+    pub epsilon_decay: u64,
+    // Transitions per training step
+    pub batch: usize,
+    // Frames before the first training step, to fill the buffer
+    pub warmup: u64,
+    // Frames between copies of the brain into the target network
+    pub sync_every: u64,
+    // Episodes per update
+    pub episodes: u32,
+    pub max_frames: u32,
+    // Updates done and frames flown in all of them
+    pub updates: u32,
+    pub frames: u64,
+    next_seed: u64,
+    rng: StdRng,
+}
+
+impl Dqn {
+    pub fn new(mut bird: Bird, course: PipeGenerator, seed: u64) -> Result<Dqn, String> {
+        if bird.brain.output_nodes != 2 {
+            return Err(format!(
+                "Q-learning needs a brain with 2 outputs, not {}",
+                bird.brain.output_nodes
+            ));
+        }
+        // Values can be any number, the highest one is flown all the same
+        bird.brain.set_output_function(LINEAR);
+        bird.brain.set_learning_rate(LEARNING_RATE);
+        Ok(Dqn {
+            target: bird.brain.clone(),
+            bird,
+            course,
+            buffer: ReplayBuffer::new(50_000),
+            reward: Reward::Shaped,
+            space: ObservationSpace::Inputs,
+            discount: 0.99,
+            epsilon_start: 1.0,
+            epsilon_end: 0.01,
+            epsilon_decay: 50_000,
+            batch: 32,
+            warmup: 1000,
+            sync_every: 1000,
+            episodes: 10,
+            max_frames: MAX_FRAMES,
+            updates: 0,
+            frames: 0,
+            next_seed: seed,
+            rng: StdRng::seed_from_u64(seed),
+        })
+    }
+
+    // Chance of a random action after the frames flown so far
+    pub fn epsilon(&self) -> f32 {
+        let done = (self.frames as f32 / self.epsilon_decay.max(1) as f32).min(1.0);
+        self.epsilon_start + (self.epsilon_end - self.epsilon_start) * done
+    }
+
+    // Random action with chance epsilon, otherwise the one worth more
+    fn choose(&mut self, inputs: &[f32]) -> Result<bool, String> {
+        if self.rng.gen_range(0.0, 1.0) < self.epsilon() {
+            return Ok(self.rng.gen_bool(RANDOM_FLAPS));
+        }
+        let values = self.bird.brain.predict(inputs)?;
+        Ok(values[1] > values[0])
+    }
+
+    // One gradient step on a batch from the replay buffer
+    fn learn(&mut self) -> Result<(), String> {
+        let brain = &self.bird.brain;
+        let mut gradients = Gradients::zero(brain);
+        let batch = self.buffer.sample(self.batch, &mut self.rng);
+        for transition in batch.iter() {
+            let target = match &transition.next {
+                Some(next) => {
+                    let values = self.target.predict(next)?;
+                    transition.reward + self.discount * values[0].max(values[1])
+                }
+                None => transition.reward,
+            };
+            let action = transition.flapped as usize;
+            let values = brain.predict(&transition.inputs)?;
+            let mut errors = [0.0, 0.0];
+            errors[action] = huber_slope(values[action] - target);
+            gradients.add(&brain.gradients(&transition.inputs, &errors)?)?;
+        }
+        gradients.scale(1.0 / batch.len().max(1) as f32);
+        self.bird.brain.descend(&gradients)
+    }
+
+    // Learns once for every frame flown, frames without a decision too
+    fn fly(&mut self, frames: u32) -> Result<(), String> {
+        for _ in 0..frames {
+            self.frames += 1;
+            // Never before the buffer holds a whole batch
+            if self.frames >= self.warmup && self.buffer.len() >= self.batch {
+                self.learn()?;
+            }
+            if self.frames % self.sync_every.max(1) == 0 {
+                self.target = self.bird.brain.clone();
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * Flies a number of episodes one after the other, learning after
+     * every frame. The environment starts over on the next course on
+     * its own, the frames until the bird sees something count too
+     */
+    pub fn update(&mut self) -> Result<GenerationStats, String> {
+        let started = Instant::now();
+        let first_seed = self.next_seed;
+        let mut env = FlappyEnv::new(&self.bird, &self.course);
+        env.reward = self.reward;
+        env.space = self.space;
+        env.max_frames = self.max_frames;
+        let mut envs = VecEnv::new(vec![env], first_seed);
+        let mut inputs = envs.reset().remove(0);
+        // Frames of the current episode learned from so far
+        let mut flown = envs.envs()[0].flight().frame;
+        self.fly(flown)?;
+        let mut scores = Vec::new();
+        let mut pipes = 0;
+        while scores.len() < self.episodes as usize {
+            let flapped = self.choose(&inputs)?;
+            let mut batch = envs.step(&[flapped.then_some(1.0)])?;
+            let info = batch.infos[0];
+            let finished = batch.finished[0].take();
+            let next = batch.observations.remove(0);
+            // Ending with a crash is final, running out of frames isn't
+            let after = match (info.outcome, &finished) {
+                (Some(_), _) => None,
+                (None, Some((_, last))) => Some(last.clone()),
+                (None, None) => Some(next.clone()),
+            };
+            self.buffer.push(Transition {
+                inputs,
+                flapped,
+                reward: batch.rewards[0],
+                next: after,
+            });
+            self.fly(info.frame - flown)?;
+            flown = info.frame;
+            if let Some((episode, _)) = finished {
+                scores.push(episode.frames as u64);
+                pipes = pipes.max(episode.pipes_passed);
+                flown = envs.envs()[0].flight().frame;
+                if scores.len() < self.episodes as usize {
+                    self.fly(flown)?;
+                }
+            }
+            inputs = next;
+        }
+        self.next_seed += self.episodes as u64;
+        self.updates += 1;
+        Ok(GenerationStats {
+            generation: self.updates,
+            pipes,
+            seconds: started.elapsed().as_secs_f32(),
+            seed: first_seed,
+            population: self.episodes as usize,
+            hidden_nodes: self.bird.brain.hidden_nodes,
+            mutation_rate: self.epsilon(),
+            frames: self.frames,
+            ..GenerationStats::default()
+        }
+        .with_scores(&scores))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flappy::testing;
+
+    fn transition(reward: f32) -> Transition {
+        Transition {
+            inputs: vec![0.5; 5],
+            flapped: false,
+            reward,
+            next: None,
+        }
+    }
+
+    #[test]
+    fn replay_buffer_forgets_the_oldest() {
+        let mut buffer = ReplayBuffer::new(3);
+        for reward in 0..5 {
+            buffer.push(transition(reward as f32));
+        }
+        assert_eq!(buffer.len(), 3);
+        let mut rng = StdRng::seed_from_u64(0);
+        let rewards: Vec<f32> = buffer
+            .sample(50, &mut rng)
+            .iter()
+            .map(|t| t.reward)
+            .collect();
+        assert!(rewards.iter().all(|&r| r >= 2.0));
+        assert!(rewards.contains(&2.0) && rewards.contains(&4.0));
+    }
+
+    #[test]
+    fn huber_loss_caps_large_errors() {
+        assert_eq!(huber_slope(0.5), 0.5);
+        assert_eq!(huber_slope(-7.0), -1.0);
+    }
+
+    #[test]
+    fn counts_every_frame_of_every_episode() {
+        let bird = testing::bird();
+        let course = testing::course();
+        let mut dqn = Dqn::new(bird, course, 0).unwrap();
+        dqn.episodes = 3;
+        let stats = dqn.update().unwrap();
+        assert_eq!(stats.frames, dqn.frames);
+        assert_eq!(dqn.frames, (stats.mean * 3.0).round() as u64);
+        assert_eq!(dqn.update().unwrap().seed, 3);
+    }
+
+    #[test]
+    fn learns_values_of_final_transitions() {
+        let bird = testing::bird();
+        let course = testing::course();
+        let mut dqn = Dqn::new(bird, course, 0).unwrap();
+        assert_eq!(dqn.epsilon(), 1.0);
+        dqn.buffer.push(transition(-1.0));
+        let inputs = vec![0.5; 5];
+        for _ in 0..2000 {
+            dqn.learn().unwrap();
+        }
+        let values = dqn.bird.brain.predict(&inputs).unwrap();
+        assert!((values[0] + 1.0).abs() < 0.05, "{:?}", values);
+    }
+}
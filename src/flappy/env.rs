@@ -0,0 +1,470 @@
+use crate::flappy::bird::Bird;
+use crate::flappy::episode::{Episode, Flight, Frame, Outcome, MAX_FRAMES};
+use crate::flappy::generator::PipeGenerator;
+use std::thread;
+
+// Shaped reward for every frame alive, every pipe passed and for crashing
+const FRAME_REWARD: f32 = 0.1;
+const PIPE_REWARD: f32 = 1.0;
+const CRASH_REWARD: f32 = -1.0;
+
+// What a frame is worth to whoever flies
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Reward {
+    // A little for staying alive, more for a pipe and a penalty for crashing
+    #[default]
+    Shaped,
+    // 1 for every frame alive, what birds are bred for
+    Frames,
+    // 1 for every pipe passed and nothing else
+    Pipes,
+}
+
+impl Reward {
+    pub fn parse(name: &str) -> Result<Reward, String> {
+        match name {
+            "shaped" => Ok(Reward::Shaped),
+            "frames" => Ok(Reward::Frames),
+            "pipes" => Ok(Reward::Pipes),
+            _ => Err(format!(
+                "Unknown reward {}, use shaped, frames or pipes",
+                name
+            )),
+        }
+    }
+
+    pub fn of(self, frame: &Frame) -> f32 {
+        let crashed = frame.outcome.is_some();
+        match self {
+            Reward::Shaped if crashed => CRASH_REWARD,
+            Reward::Shaped => FRAME_REWARD + PIPE_REWARD * frame.passed as f32,
+            Reward::Frames if crashed => 0.0,
+            Reward::Frames => 1.0,
+            Reward::Pipes => frame.passed as f32,
+        }
+    }
+}
+
+// What an observation holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ObservationSpace {
+    // The inputs a brain gets, normalized like the bird's normalizer does
+    #[default]
+    Inputs,
+    // The sensor readings in pixels and frames
+    Raw,
+}
+
+impl ObservationSpace {
+    pub fn parse(name: &str) -> Result<ObservationSpace, String> {
+        match name {
+            "inputs" => Ok(ObservationSpace::Inputs),
+            "raw" => Ok(ObservationSpace::Raw),
+            _ => Err(format!(
+                "Unknown observation space {}, use inputs or raw",
+                name
+            )),
+        }
+    }
+}
+
+// What else a step tells besides the observation, reward and whether it's done
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Info {
+    // Frames flown and pipes passed since the reset
+    pub frame: u32,
+    pub pipes_passed: u32,
+    // How the bird crashed, if it did
+    pub outcome: Option<Outcome>,
+    // Done because it ran out of frames, the next observation still counts
+    pub truncated: bool,
+}
+
+/**
+ * One bird on one pipe course, driven from outside: reset it onto a
+ * course, then step it with a flap or not until it's done. Frames
+ * where the bird has nothing to see yet go by on their own, their
+ * rewards counting towards the step before
+ */
+#[derive(Debug, Clone)]
+pub struct FlappyEnv {
+    // Brain, sensors and physics every reset starts from
+    bird: Bird,
+    course: PipeGenerator,
+    pub reward: Reward,
+    pub space: ObservationSpace,
+    pub max_frames: u32,
+    flight: Flight,
+    // Kept for steps that end with nothing to see
+    last: Vec<f32>,
+}
+
+impl FlappyEnv {
+    pub fn new(bird: &Bird, course: &PipeGenerator) -> FlappyEnv {
+        FlappyEnv {
+            bird: bird.reborn(),
+            course: course.clone(),
+            reward: Reward::default(),
+            space: ObservationSpace::default(),
+            max_frames: MAX_FRAMES,
+            flight: Flight::new(bird, course, 0),
+            last: vec![0.0; bird.sensors.size()],
+        }
+    }
+
+    // The bird and pipes as they are, for drawing or looking closer
+    pub fn flight(&self) -> &Flight {
+        &self.flight
+    }
+
+    pub fn done(&self) -> bool {
+        self.flight.outcome.is_some() || self.flight.frame >= self.max_frames
+    }
+
+    // How far the bird got so far
+    pub fn episode(&self) -> Episode {
+        self.flight.episode()
+    }
+
+    // Starts over on the course of seed, with the first thing the bird sees
+    pub fn reset(&mut self, seed: u64) -> Vec<f32> {
+        self.flight = Flight::new(&self.bird, &self.course, seed);
+        self.flight.move_pipes();
+        self.settle(0.0).0
+    }
+
+    // One decision, flapping as hard as action says or gliding with None
+    pub fn step(&mut self, action: Option<f32>) -> (Vec<f32>, f32, bool, Info) {
+        if self.done() {
+            return (self.last.clone(), 0.0, true, self.info());
+        }
+        if let Some(strength) = action {
+            self.flight.bird.flap(strength);
+        }
+        let reward = self.advance();
+        let (observation, reward) = self.settle(reward);
+        (observation, reward, self.done(), self.info())
+    }
+
+    // What the bird's own brain and action head would do with an observation
+    pub fn act(&mut self, observation: &[f32]) -> Option<f32> {
+        self.flight.bird.act(observation)
+    }
+
+    fn info(&self) -> Info {
+        Info {
+            frame: self.flight.frame,
+            pipes_passed: self.flight.pipes_passed,
+            outcome: self.flight.outcome,
+            truncated: self.flight.outcome.is_none() && self.done(),
+        }
+    }
+
+    // The rest of a frame after the decision, and the start of the next
+    fn advance(&mut self) -> f32 {
+        let frame = self.flight.move_bird(None);
+        if !self.done() {
+            self.flight.move_pipes();
+        }
+        self.reward.of(&frame)
+    }
+
+    // Flies on without flapping until there's something to see
+    fn settle(&mut self, mut reward: f32) -> (Vec<f32>, f32) {
+        loop {
+            if let Some(observation) = self.observe() {
+                self.last = observation.clone();
+                return (observation, reward);
+            }
+            if self.done() {
+                return (self.last.clone(), reward);
+            }
+            reward += self.advance();
+        }
+    }
+
+    fn observe(&mut self) -> Option<Vec<f32>> {
+        let bird = &mut self.flight.bird;
+        let raw = bird.sensors.read(bird, &self.flight.pipes)?;
+        match self.space {
+            ObservationSpace::Inputs => {
+                bird.normalizer.observe(&raw);
+                Some(bird.normalizer.normalize(&raw))
+            }
+            ObservationSpace::Raw => Some(raw),
+        }
+    }
+}
+
+// One step of every environment of a VecEnv
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Batch {
+    pub observations: Vec<Vec<f32>>,
+    pub rewards: Vec<f32>,
+    pub dones: Vec<bool>,
+    pub infos: Vec<Info>,
+    // For environments that were reset, how the episode went and what was seen last
+    pub finished: Vec<Option<(Episode, Vec<f32>)>>,
+}
+
+// What a step of one environment returns
+type Step = (Vec<f32>, f32, bool, Info);
+
+/**
+ * One step of every environment, the environments split over
+ * threads. Every environment only steps itself, so the steps are
+ * the same however many threads share the work
+ */
+fn step_all(envs: &mut [FlappyEnv], actions: &[Option<f32>], threads: usize) -> Vec<Step> {
+    let step = |envs: &mut [FlappyEnv], actions: &[Option<f32>]| -> Vec<Step> {
+        envs.iter_mut()
+            .zip(actions.iter())
+            .map(|(env, &action)| env.step(action))
+            .collect()
+    };
+    if threads <= 1 {
+        return step(envs, actions);
+    }
+    let chunk = ((envs.len() + threads - 1) / threads).max(1);
+    thread::scope(|scope| {
+        let handles: Vec<_> = envs
+            .chunks_mut(chunk)
+            .zip(actions.chunks(chunk))
+            .map(|(envs, actions)| scope.spawn(move || step(envs, actions)))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    })
+}
+
+// One episode of every environment on the course of its seed, start to end
+fn fly_all(envs: &mut [FlappyEnv], seeds: &[u64]) -> Vec<Episode> {
+    envs.iter_mut()
+        .zip(seeds.iter())
+        .map(|(env, &seed)| {
+            let mut observation = env.reset(seed);
+            while !env.done() {
+                let action = env.act(&observation);
+                observation = env.step(action).0;
+            }
+            env.episode()
+        })
+        .collect()
+}
+
+/**
+ * Several environments stepped together, one action each. An
+ * environment that's done starts over on the next seed right away,
+ * so the observation it returns is the first of its new episode
+ */
+#[derive(Debug, Clone)]
+pub struct VecEnv {
+    envs: Vec<FlappyEnv>,
+    // Threads step and fly share the environments between
+    pub threads: usize,
+    // Without it finished environments stay done until the next reset
+    pub auto_reset: bool,
+    next_seed: u64,
+}
+
+impl VecEnv {
+    // Courses are seeded counting up from seed
+    pub fn new(envs: Vec<FlappyEnv>, seed: u64) -> VecEnv {
+        VecEnv {
+            envs,
+            threads: 1,
+            auto_reset: true,
+            next_seed: seed,
+        }
+    }
+
+    // An environment for every bird, each on its own course
+    pub fn population(birds: &[Bird], course: &PipeGenerator, seed: u64) -> VecEnv {
+        let envs = birds
+            .iter()
+            .map(|bird| FlappyEnv::new(bird, course))
+            .collect();
+        VecEnv::new(envs, seed)
+    }
+
+    pub fn envs(&self) -> &[FlappyEnv] {
+        &self.envs
+    }
+
+    pub fn envs_mut(&mut self) -> &mut [FlappyEnv] {
+        &mut self.envs
+    }
+
+    // Starts every environment over on a new course
+    pub fn reset(&mut self) -> Vec<Vec<f32>> {
+        let mut observations = Vec::with_capacity(self.envs.len());
+        for env in self.envs.iter_mut() {
+            observations.push(env.reset(self.next_seed));
+            self.next_seed += 1;
+        }
+        observations
+    }
+
+    pub fn step(&mut self, actions: &[Option<f32>]) -> Result<Batch, String> {
+        if actions.len() != self.envs.len() {
+            return Err(format!(
+                "{} actions for {} environments",
+                actions.len(),
+                self.envs.len()
+            ));
+        }
+        let steps = step_all(&mut self.envs, actions, self.threads);
+        let mut batch = Batch::default();
+        for (env, (mut observation, reward, done, info)) in self.envs.iter_mut().zip(steps) {
+            let mut finished = None;
+            if done && self.auto_reset {
+                finished = Some((env.episode(), observation));
+                observation = env.reset(self.next_seed);
+                self.next_seed += 1;
+            }
+            batch.observations.push(observation);
+            batch.rewards.push(reward);
+            batch.dones.push(done);
+            batch.infos.push(info);
+            batch.finished.push(finished);
+        }
+        Ok(batch)
+    }
+
+    // What every bird's own brain would do, gliding once it's done
+    pub fn act(&mut self, observations: &[Vec<f32>]) -> Vec<Option<f32>> {
+        self.envs
+            .iter_mut()
+            .zip(observations.iter())
+            .map(|(env, observation)| match env.done() {
+                true => None,
+                false => env.act(observation),
+            })
+            .collect()
+    }
+
+    // Flies every bird once with its own brain, each on a new course
+    pub fn fly(&mut self) -> Vec<Episode> {
+        let seeds: Vec<u64> = (0..self.envs.len() as u64)
+            .map(|i| self.next_seed + i)
+            .collect();
+        self.next_seed += seeds.len() as u64;
+        self.fly_on(&seeds)
+    }
+
+    /**
+     * Flies every bird once with its own brain on the course of its
+     * seed. Whole episodes are spread over the threads, nothing waits
+     * for the others until the last bird is done
+     */
+    pub fn fly_on(&mut self, seeds: &[u64]) -> Vec<Episode> {
+        if self.threads <= 1 {
+            return fly_all(&mut self.envs, seeds);
+        }
+        let chunk = ((self.envs.len() + self.threads - 1) / self.threads).max(1);
+        thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .envs
+                .chunks_mut(chunk)
+                .zip(seeds.chunks(chunk))
+                .map(|(envs, seeds)| scope.spawn(move || fly_all(envs, seeds)))
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flappy::episode;
+    use crate::flappy::testing;
+
+    #[test]
+    fn flies_like_an_episode() {
+        let bird = testing::bird();
+        let course = testing::course();
+        let mut env = FlappyEnv::new(&bird, &course);
+        env.reward = Reward::Frames;
+        env.max_frames = 2000;
+        let mut observation = env.reset(7);
+        let start = env.flight().frame;
+        let mut total = 0.0;
+        let crashed = loop {
+            let action = env.act(&observation);
+            let (next, reward, done, info) = env.step(action);
+            total += reward;
+            observation = next;
+            if done {
+                assert_eq!(info.truncated, info.outcome.is_none());
+                break info.outcome.is_some();
+            }
+        };
+        let flown = env.episode();
+        assert_eq!(flown, episode::run(&bird, &course, 7, 2000));
+        assert_eq!(total as u32, flown.frames - start - crashed as u32);
+    }
+
+    #[test]
+    fn observes_raw_readings() {
+        let bird = testing::bird();
+        let course = testing::course();
+        let mut env = FlappyEnv::new(&bird, &course);
+        env.space = ObservationSpace::Raw;
+        let observation = env.reset(0);
+        assert_eq!(observation.len(), bird.sensors.size());
+        assert_eq!(observation[3], env.flight().bird.y);
+    }
+
+    #[test]
+    fn steps_environments_together() {
+        let birds: Vec<Bird> = (0..6).map(|_| testing::bird()).collect();
+        let course = testing::course();
+        let mut envs = VecEnv::population(&birds, &course, 10);
+        envs.threads = 3;
+        let episodes = envs.fly();
+        for (i, (bird, flown)) in birds.iter().zip(episodes.iter()).enumerate() {
+            assert_eq!(
+                *flown,
+                episode::run(bird, &course, 10 + i as u64, MAX_FRAMES)
+            );
+        }
+
+        // Gliding birds all crash into the floor and start over on the next course
+        let mut envs = VecEnv::population(&birds[..2], &course, 0);
+        envs.reset();
+        let batch = loop {
+            let batch = envs.step(&[None, None]).unwrap();
+            if batch.dones.iter().any(|&done| done) {
+                break batch;
+            }
+        };
+        let (flown, _) = batch.finished[0].clone().unwrap();
+        assert_eq!(flown.outcome, Outcome::Floor);
+        assert_eq!(envs.envs()[0].episode().seed, 2);
+        assert!(envs.step(&[None]).is_err());
+    }
+
+    #[test]
+    fn threads_step_the_same() {
+        let birds: Vec<Bird> = (0..5).map(|_| testing::bird()).collect();
+        let course = testing::course();
+        let mut sequential = VecEnv::population(&birds, &course, 3);
+        let mut threaded = sequential.clone();
+        threaded.threads = 2;
+        let mut observations = sequential.reset();
+        assert_eq!(threaded.reset(), observations);
+        // Long enough for some birds to crash and start over
+        for _ in 0..300 {
+            let actions = sequential.act(&observations);
+            let batch = sequential.step(&actions).unwrap();
+            assert_eq!(threaded.step(&actions).unwrap(), batch);
+            observations = batch.observations;
+        }
+    }
+}
@@ -0,0 +1,376 @@
+use crate::flappy::bird::Bird;
+use crate::flappy::env::VecEnv;
+use crate::flappy::generator::PipeGenerator;
+use crate::flappy::pipe::{Pipe, SPEED};
+use std::thread;
+
+// Episodes stop here, a bird that gets this far can't be told apart anyway
+pub const MAX_FRAMES: u32 = 5000;
+
+// How an episode ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Outcome {
+    // Still flying after the last frame
+    Survived,
+    TopPipe,
+    BottomPipe,
+    Ceiling,
+    Floor,
+}
+
+impl Outcome {
+    pub fn name(self) -> &'static str {
+        match self {
+            Outcome::Survived => "survived",
+            Outcome::TopPipe => "top pipe",
+            Outcome::BottomPipe => "bottom pipe",
+            Outcome::Ceiling => "ceiling",
+            Outcome::Floor => "floor",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Episode {
+    pub seed: u64,
+    // Frames survived, the score of the game
+    pub frames: u32,
+    pub pipes_passed: u32,
+    pub outcome: Outcome,
+}
+
+// What happened in one frame of an episode, for learning from it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    // Inputs the bird decided on and whether it flapped, if it had to decide
+    pub decision: Option<(Vec<f32>, bool)>,
+    // Pipes passed in this frame
+    pub passed: u32,
+    // How the episode ended, on its last frame
+    pub outcome: Option<Outcome>,
+}
+
+// Where the pipes of a flight come from
+#[derive(Debug, Clone)]
+enum Course {
+    // Made up as the bird flies from a seed, boxed since it's big
+    Generated(Box<PipeGenerator>),
+    // Every pipe of a recording with the frame it came at
+    Recorded(Vec<(u32, Pipe)>),
+}
+
+/**
+ * A bird flying a pipe course one frame at a time. This is the
+ * one place a frame of the game happens, training, playing and
+ * headless episodes and replays all fly their birds through it,
+ * so the same seed and pipes always give the same course
+ */
+#[derive(Debug, Clone)]
+pub struct Flight {
+    pub bird: Bird,
+    pub pipes: Vec<Pipe>,
+    course: Course,
+    pub seed: u64,
+    // Frames flown and pipes passed so far
+    pub frame: u32,
+    pub pipes_passed: u32,
+    pub outcome: Option<Outcome>,
+    // Pixels the course moved left so far, for the background to follow
+    scrolled: f32,
+    // Every pipe spawned and the frame it came, for recordings
+    pub spawned: Vec<(u32, Pipe)>,
+}
+
+impl Flight {
+    // A copy of bird, with its brain, senses and physics, at the start of the course of seed
+    pub fn new(bird: &Bird, course: &PipeGenerator, seed: u64) -> Flight {
+        let mut bird = bird.reborn();
+        bird.seed_policy(seed);
+        Flight::start(bird, course, seed)
+    }
+
+    // The bird itself at the start of the course of seed, keeping its id and family
+    pub fn start(bird: Bird, course: &PipeGenerator, seed: u64) -> Flight {
+        Flight::on(bird, Course::Generated(Box::new(course.clone())), seed)
+    }
+
+    // The bird itself through the pipes of a recording of the course of seed
+    pub fn replay(bird: Bird, pipes: Vec<(u32, Pipe)>, seed: u64) -> Flight {
+        Flight::on(bird, Course::Recorded(pipes), seed)
+    }
+
+    fn on(bird: Bird, course: Course, seed: u64) -> Flight {
+        let mut flight = Flight {
+            bird,
+            pipes: Vec::new(),
+            course,
+            seed,
+            frame: 0,
+            pipes_passed: 0,
+            outcome: None,
+            scrolled: 0.0,
+            spawned: Vec::new(),
+        };
+        flight.restart(seed);
+        flight
+    }
+
+    // Back to the start of the course of seed, the same bird flying again
+    pub fn restart(&mut self, seed: u64) {
+        self.bird.restart();
+        if let Course::Generated(generator) = &mut self.course {
+            generator.restart(seed);
+        }
+        self.pipes.clear();
+        self.spawned.clear();
+        self.seed = seed;
+        self.frame = 0;
+        self.pipes_passed = 0;
+        self.outcome = None;
+        self.scrolled = 0.0;
+    }
+
+    /**
+     * One frame. decide gets the bird before it moves and the pipes
+     * it sees, flaps it or not, and returns the inputs it decided
+     * on and whether it flapped, if it had to decide
+     */
+    pub fn step<F>(&mut self, decide: F) -> Frame
+    where
+        F: FnOnce(&mut Bird, &[Pipe]) -> Option<(Vec<f32>, bool)>,
+    {
+        self.move_pipes();
+        let decision = decide(&mut self.bird, &self.pipes);
+        self.move_bird(decision)
+    }
+
+    // First half of a frame, the pipes move on before the bird decides
+    pub fn move_pipes(&mut self) {
+        self.scrolled += self.speed();
+        for pipe in self.pipes.iter_mut() {
+            pipe.update();
+        }
+        self.pipes.retain(|pipe| !pipe.offscreen());
+    }
+
+    // Second half of a frame, the bird moves and crashes or passes pipes
+    pub fn move_bird(&mut self, decision: Option<(Vec<f32>, bool)>) -> Frame {
+        self.bird.update();
+        let bird = &self.bird;
+        let passed = self.pipes.iter().filter(|p| p.passed(bird)).count() as u32;
+        self.pipes_passed += passed;
+
+        self.outcome = if self.pipes.iter().any(|p| p.hits_top(bird)) {
+            Some(Outcome::TopPipe)
+        } else if self.pipes.iter().any(|p| p.hits_bottom(bird)) {
+            Some(Outcome::BottomPipe)
+        } else if bird.hits_ceiling() {
+            Some(Outcome::Ceiling)
+        } else if bird.hits_floor() {
+            Some(Outcome::Floor)
+        } else {
+            None
+        };
+        if self.outcome.is_none() {
+            while let Some(pipe) = self.spawn() {
+                self.spawned.push((self.frame, pipe.clone()));
+                self.pipes.push(pipe);
+            }
+        }
+        self.frame += 1;
+        Frame {
+            decision,
+            passed,
+            outcome: self.outcome,
+        }
+    }
+
+    // Pixels a frame the course moves at now, sped up by the curriculum or as recorded
+    pub fn speed(&self) -> f32 {
+        match &self.course {
+            Course::Generated(generator) => generator.config(self.pipes_passed).speed,
+            Course::Recorded(pipes) => self
+                .spawned
+                .last()
+                .or_else(|| pipes.first())
+                .map_or(SPEED, |(_, pipe)| pipe.speed),
+        }
+    }
+
+    // How far the course scrolled, alpha of the way to the next frame
+    pub fn scrolled(&self, alpha: f32) -> f32 {
+        self.scrolled + alpha * self.speed()
+    }
+
+    // The next pipe due at this frame, if any
+    fn spawn(&mut self) -> Option<Pipe> {
+        match &mut self.course {
+            Course::Generated(generator) => generator.spawn(self.frame, self.pipes_passed),
+            Course::Recorded(pipes) => match pipes.get(self.spawned.len()) {
+                Some((frame, pipe)) if *frame == self.frame => Some(pipe.clone()),
+                _ => None,
+            },
+        }
+    }
+
+    // How far it got, still flying counts as survived
+    pub fn episode(&self) -> Episode {
+        Episode {
+            seed: self.seed,
+            frames: self.frame,
+            pipes_passed: self.pipes_passed,
+            outcome: self.outcome.unwrap_or(Outcome::Survived),
+        }
+    }
+}
+
+// Flies a copy of bird through the pipe course of seed, the brain deciding
+pub fn run(bird: &Bird, course: &PipeGenerator, seed: u64, max_frames: u32) -> Episode {
+    watch(bird, course, seed, max_frames, |_| {})
+}
+
+// Like run, showing every frame to on_frame as it happens
+pub fn watch<F: FnMut(&Frame)>(
+    bird: &Bird,
+    course: &PipeGenerator,
+    seed: u64,
+    max_frames: u32,
+    mut on_frame: F,
+) -> Episode {
+    let mut flight = Flight::new(bird, course, seed);
+    while flight.frame < max_frames && flight.outcome.is_none() {
+        on_frame(&flight.step(|bird, pipes| bird.think(pipes)));
+    }
+    flight.episode()
+}
+
+// How the scores of several episodes become one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aggregate {
+    Mean,
+    // The score a bird reaches in all but this fraction of its
+    // episodes, rewards being reliable over being lucky once
+    Quantile(f32),
+}
+
+impl Aggregate {
+    pub fn apply(self, scores: &[f32]) -> f32 {
+        if scores.is_empty() {
+            return 0.0;
+        }
+        match self {
+            Aggregate::Mean => scores.iter().sum::<f32>() / scores.len() as f32,
+            Aggregate::Quantile(q) => {
+                let mut sorted = scores.to_vec();
+                sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                let i = (q.clamp(0.0, 1.0) * (sorted.len() - 1) as f32) as usize;
+                sorted[i]
+            }
+        }
+    }
+}
+
+/**
+ * Scores every brain over several pipe courses instead of the
+ * one it happened to fly, so lucky birds don't get picked
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Evaluation {
+    pub episodes: u32,
+    // Every bird flies the same courses, or each its own
+    pub same_seeds: bool,
+    pub aggregate: Aggregate,
+    pub max_frames: u32,
+}
+
+impl Evaluation {
+    pub fn new(episodes: u32) -> Evaluation {
+        Evaluation {
+            episodes: episodes.max(1),
+            same_seeds: false,
+            aggregate: Aggregate::Mean,
+            max_frames: MAX_FRAMES,
+        }
+    }
+
+    // Fitness of every bird, spread over all cores, and frames flown by all
+    pub fn score_all(&self, birds: &[Bird], course: &PipeGenerator) -> (Vec<f32>, u64) {
+        let mut envs = VecEnv::population(birds, course, rand::random());
+        envs.threads = thread::available_parallelism().map_or(1, |n| n.get());
+        for env in envs.envs_mut() {
+            env.max_frames = self.max_frames;
+        }
+        let mut frames = vec![Vec::new(); birds.len()];
+        for _ in 0..self.episodes {
+            let episodes = match self.same_seeds {
+                true => envs.fly_on(&vec![rand::random(); birds.len()]),
+                false => envs.fly(),
+            };
+            for (frames, episode) in frames.iter_mut().zip(episodes) {
+                frames.push(episode.frames as f32);
+            }
+        }
+        let flown = frames.iter().flatten().map(|&f| f as u64).sum();
+        let scores = frames.iter().map(|f| self.aggregate.apply(f)).collect();
+        (scores, flown)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flappy::pipe::PipeConfig;
+    use crate::flappy::testing;
+
+    #[test]
+    fn same_seed_flies_the_same_course() {
+        let bird = testing::bird();
+        let course = testing::course();
+        let first = run(&bird, &course, 42, 2000);
+        assert_eq!(first, run(&bird, &course, 42, 2000));
+        assert!(first.frames <= 2000);
+    }
+
+    #[test]
+    fn restarted_flight_flies_the_same_course() {
+        let bird = testing::bird();
+        let course = testing::course();
+        let mut flight = Flight::new(&bird, &course, 42);
+        let fly = |flight: &mut Flight| {
+            while flight.outcome.is_none() && flight.frame < 2000 {
+                flight.step(|bird, pipes| bird.think(pipes));
+            }
+            let spawned: Vec<u32> = flight.spawned.iter().map(|&(frame, _)| frame).collect();
+            (flight.episode(), spawned)
+        };
+        let first = fly(&mut flight);
+        flight.restart(42);
+        assert_eq!(first, fly(&mut flight));
+        assert_eq!(first.0, run(&bird, &course, 42, 2000));
+    }
+
+    #[test]
+    fn scrolls_at_the_pipe_speed() {
+        let bird = testing::bird();
+        let config = PipeConfig {
+            speed: 4.0,
+            ..PipeConfig::default()
+        };
+        let course = PipeGenerator::new(800, 600, 0, config);
+        let mut flight = Flight::new(&bird, &course, 42);
+        for _ in 0..5 {
+            flight.step(|_, _| None);
+        }
+        assert_eq!(flight.scrolled(0.5), 22.0);
+        flight.restart(42);
+        assert_eq!(flight.scrolled(0.0), 0.0);
+    }
+
+    #[test]
+    fn aggregates_scores() {
+        let scores = [40.0, 10.0, 30.0, 20.0, 50.0];
+        assert_eq!(Aggregate::Mean.apply(&scores), 30.0);
+        assert_eq!(Aggregate::Quantile(0.0).apply(&scores), 10.0);
+        assert_eq!(Aggregate::Quantile(0.5).apply(&scores), 30.0);
+    }
+}
@@ -1,121 +1,595 @@
 #[allow(dead_code)]
 #[allow(unused_variables)]
-use crate::flappy::bird::Bird;
-use crate::flappy::pipe::Pipe;
+use crate::flappy::action::ActionHead;
+use crate::flappy::bird::{Bird, MUTATION_RATE, MUTATION_SIZE};
+use crate::flappy::diversity;
+use crate::flappy::episode::{Evaluation, Flight};
+use crate::flappy::genealogy::Genealogy;
+use crate::flappy::generator::{Curriculum, PipeGenerator};
+use crate::flappy::normalize::{self, Normalizer};
+use crate::flappy::physics::PhysicsConfig;
+use crate::flappy::pipe::PipeConfig;
+use crate::flappy::recording::Recording;
+use crate::flappy::sensors::{Limits, SensorSet};
+use crate::flappy::stats::{GenerationStats, Stats};
+use crate::flappy::theme::{lineage_color, rank_color, Theme};
+use crate::flappy::vision::{self, DEFAULT_RAYS};
 use crate::nn::nn::NeuralNetwork;
+use crate::renderer::target::DrawTarget;
 use rand::Rng;
-use sdl2::render::Canvas;
-use sdl2::video::Window;
+use sdl2::pixels::Color;
+use std::collections::HashSet;
+use std::time::Instant;
+
+// Upper bound of simulation steps per frame
+const MAX_CYCLE_SPEED: i32 = 100;
+// Birds per generation
+const POPULATION: usize = 500;
+pub const HIDDEN_NODES: i32 = 8;
+// Highest mutation rate stagnation can push it to
+const MAX_MUTATION_RATE: f32 = 0.5;
+// Random birds brought in against stagnation
+const IMMIGRANTS: usize = POPULATION / 5;
+
+// How the birds are told apart on screen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Coloring {
+    // All the same, the original look
+    Plain,
+    // One color per family, shrinking variety means lost diversity
+    Lineage,
+    // Green to red by the fitness of their parents
+    Rank,
+}
+
+// What to do when the best score stops improving
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reaction {
+    // Double the mutation rate until there's a new best score
+    Mutate,
+    // Replace part of the next generation with random birds
+    Immigrants,
+    // Throw everyone away and start from random birds
+    Restart,
+}
+
+impl Reaction {
+    pub fn parse(name: &str) -> Result<Reaction, String> {
+        match name {
+            "mutate" => Ok(Reaction::Mutate),
+            "immigrants" => Ok(Reaction::Immigrants),
+            "restart" => Ok(Reaction::Restart),
+            _ => Err(format!(
+                "Unknown reaction {}, use mutate, immigrants or restart",
+                name
+            )),
+        }
+    }
+}
+
+impl Coloring {
+    pub fn parse(name: &str) -> Result<Coloring, String> {
+        match name {
+            "plain" => Ok(Coloring::Plain),
+            "lineage" => Ok(Coloring::Lineage),
+            "rank" => Ok(Coloring::Rank),
+            _ => Err(format!(
+                "Unknown coloring {}, use plain, lineage or rank",
+                name
+            )),
+        }
+    }
+
+    pub fn next(self) -> Coloring {
+        match self {
+            Coloring::Plain => Coloring::Lineage,
+            Coloring::Lineage => Coloring::Rank,
+            Coloring::Rank => Coloring::Plain,
+        }
+    }
+}
 
 pub struct Game {
     counter: u32,
     best_bird: Option<usize>,
     high_score: u32,
+    // Best score among the birds still flying
+    current_score: u32,
     run_best: bool,
+    // Indices of the flights still going
     active_birds: Vec<usize>,
+    // The population between generations, off flying while one runs
     all_birds: Vec<Bird>,
-    pipes: Vec<Pipe>,
+    // Every bird on its own copy of the course of the current run
+    flights: Vec<Flight>,
     width: u32,
     height: u32,
     cycle_speed: i32,
+    coloring: Coloring,
+    generation: u32,
+    // Pipe course of the current generation
+    generator: PipeGenerator,
+    // Seed of the pipe course of the current run
+    seed: u64,
+    // Longest run of the current generation
+    generation_best: Option<Recording>,
+    // Where to save the best run of every generation
+    record_dir: Option<String>,
+    // Every bird that finished a run
+    genealogy: Genealogy,
+    // Id and score of the best bird of all generations
+    champion: Option<(u64, u64)>,
+    // Where to export the whole genealogy and the champion's ancestry
+    genealogy_out: Option<String>,
+    ancestry_out: Option<String>,
+    // Where the brain of the best bird so far is kept
+    brain_out: Option<String>,
+    // Most pipes any bird passed in the current generation
+    pipes_passed: u32,
+    // When the current generation started
+    started: Instant,
+    stats: Option<Stats>,
+    // Chance of every weight to change when breeding
+    mutation_rate: f32,
+    // Of the last generation, see the diversity module
+    genotype_diversity: f32,
+    behaviour_diversity: f32,
+    // Best score of all generations, and generations since it was beaten
+    best_score: u64,
+    stagnant: u32,
+    // Generations without progress to put up with, and what to do then
+    stagnation: Option<(u32, Reaction)>,
+    // Family for the next bird that doesn't descend from anyone
+    next_lineage: u32,
+    // Fitness from several headless episodes instead of the one run
+    evaluation: Option<Evaluation>,
+    // What every bird's brain gets as inputs
+    sensors: SensorSet,
+    // Inputs every bird saw in all generations so far
+    normalizer: Normalizer,
+    // How every bird falls and flaps
+    physics: PhysicsConfig,
+    // How the outputs of every brain become a flap
+    head: ActionHead,
+    // Frames flown by all birds of all generations, episodes included
+    frames: u64,
+    // Print what the inputs looked like after every generation
+    input_stats: bool,
+    // Draw what the leading bird sees with its rays
+    show_rays: bool,
 }
 
 impl Game {
     pub fn new(width: u32, height: u32) -> Game {
-        let total_population = 500;
-        let mut active_birds: Vec<usize> = Vec::new();
         let mut all_birds = Vec::new();
 
-        for i in 0..total_population {
-            let bird = Bird::new(width, height, NeuralNetwork::new(5, 8, 2));
+        let sensors = SensorSet::default();
+        for i in 0..POPULATION {
+            let brain = NeuralNetwork::new(sensors.size() as i32, HIDDEN_NODES, 2);
+            let mut bird = Bird::new(width, height, brain);
+            // Every first bird starts a family of its own
+            bird.lineage = i as u32;
             all_birds.push(bird);
-            active_birds.push(i);
         }
 
+        let seed = rand::random();
         Game {
-            pipes: Vec::new(),
+            flights: Vec::new(),
             counter: 0,
             best_bird: None,
             high_score: 0,
+            current_score: 0,
             run_best: false,
             width,
             height,
             all_birds,
-            active_birds,
+            active_birds: Vec::new(),
             cycle_speed: 1,
+            coloring: Coloring::Plain,
+            generation: 0,
+            generator: PipeGenerator::new(width, height, seed, PipeConfig::default()),
+            seed,
+            generation_best: None,
+            record_dir: None,
+            genealogy: Genealogy::new(),
+            champion: None,
+            genealogy_out: None,
+            ancestry_out: None,
+            brain_out: None,
+            pipes_passed: 0,
+            started: Instant::now(),
+            stats: None,
+            mutation_rate: MUTATION_RATE,
+            genotype_diversity: 0.0,
+            behaviour_diversity: 0.0,
+            best_score: 0,
+            stagnant: 0,
+            stagnation: None,
+            next_lineage: POPULATION as u32,
+            evaluation: None,
+            normalizer: Normalizer::new(
+                &sensors,
+                &Limits::new(
+                    width,
+                    height,
+                    &PhysicsConfig::default(),
+                    &PipeConfig::default(),
+                ),
+            ),
+            input_stats: false,
+            physics: PhysicsConfig::default(),
+            head: ActionHead::default(),
+            frames: 0,
+            sensors,
+            show_rays: false,
         }
     }
 
-    pub fn draw(&mut self, canvas: &mut Canvas<Window>) -> Result<(), String> {
-        //println!("RENDER");
-        for _ in 0..self.cycle_speed {
-            canvas.set_draw_color(sdl2::pixels::Color::RGB(0, 0, 0));
-            canvas.clear();
-            canvas.set_draw_color(sdl2::pixels::Color::RGB(255, 255, 255));
-            // Show all the pipes
-            let acc: Vec<Pipe> = Vec::new();
-            let new_pipes = self.pipes.iter().fold(acc, |acc, pipe| {
-                let mut new_pipe = pipe.clone();
-                new_pipe.update();
-                if new_pipe.offscreen() {
-                    return acc;
-                }
-                let mut new_acc = acc.clone();
-                new_acc.push(new_pipe);
-                new_acc
-            });
-            self.pipes = new_pipes.clone();
-
-            if self.run_best {
-                if self.best_bird.is_some() {
-                    let best_index = self.best_bird.unwrap();
-                    self.all_birds[best_index].think(&new_pipes);
-                    self.all_birds[best_index].update();
-                    for j in 0..new_pipes.len() {
-                        // Start over, bird hit pipe
-                        if new_pipes[j].hits(&self.all_birds[best_index]) {
-                            self.reset_game();
-                        }
-                    }
-                    self.best_bird = Some(best_index);
-                    if self.all_birds[best_index].bottom_top() {
-                        self.reset_game();
-                    }
+    // Save the best run of every generation as a recording in dir
+    pub fn record_to(&mut self, dir: &str) -> Result<(), String> {
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        self.record_dir = Some(dir.to_string());
+        Ok(())
+    }
+
+    // Export every bird after each generation, as .dot or .json
+    pub fn export_genealogy_to(&mut self, path: &str) {
+        self.genealogy_out = Some(path.to_string());
+    }
+
+    // Export the family tree of the best bird so far after each generation
+    pub fn export_ancestry_to(&mut self, path: &str) {
+        self.ancestry_out = Some(path.to_string());
+    }
+
+    // Save the brain of every new best bird to path, for evaluate
+    pub fn save_brain_to(&mut self, path: &str) {
+        self.brain_out = Some(path.to_string());
+    }
+
+    // Log a summary of every generation to a .csv or .jsonl file
+    pub fn log_stats_to(&mut self, path: &str) -> Result<(), String> {
+        self.stats = Some(Stats::create(path)?);
+        Ok(())
+    }
+
+    fn log_stats(&mut self) {
+        let stats = match &mut self.stats {
+            Some(stats) => stats,
+            None => return,
+        };
+        let scores: Vec<u64> = self.all_birds.iter().map(|b| b.score).collect();
+        let lineages: HashSet<u32> = self.all_birds.iter().map(|b| b.lineage).collect();
+        let record = GenerationStats {
+            generation: self.generation,
+            pipes: self.pipes_passed,
+            level: self.generator.level(self.pipes_passed),
+            diversity: lineages.len(),
+            seconds: self.started.elapsed().as_secs_f32(),
+            seed: self.seed,
+            population: POPULATION,
+            hidden_nodes: HIDDEN_NODES,
+            genotype_diversity: self.genotype_diversity,
+            behaviour_diversity: self.behaviour_diversity,
+            stagnant: self.stagnant,
+            mutation_rate: self.mutation_rate,
+            mutation_size: MUTATION_SIZE,
+            frames: self.frames,
+            ..GenerationStats::default()
+        }
+        .with_scores(&scores);
+        if let Err(e) = stats.log(&record) {
+            println!("{}", e);
+        }
+    }
+
+    // React after generations generations without a better best score
+    pub fn on_stagnation(&mut self, generations: u32, reaction: Reaction) {
+        self.stagnation = Some((generations.max(1), reaction));
+    }
+
+    // Gap, speed, width and interval of the pipes before any curriculum
+    pub fn set_pipe_config(&mut self, config: PipeConfig) {
+        for bird in self.all_birds.iter_mut() {
+            bird.set_pipe_config(config);
+        }
+        self.generator.base = config;
+        self.normalizer.set_limits(&self.sensors, &self.limits());
+    }
+
+    // How far the readings of every bird can go
+    fn limits(&self) -> Limits {
+        Limits::new(self.width, self.height, &self.physics, &self.generator.base)
+    }
+
+    pub fn set_curriculum(&mut self, curriculum: Option<Curriculum>) {
+        self.generator.set_curriculum(curriculum);
+    }
+
+    /**
+     * Gives every bird these sensors and a new random brain
+     * with an input for each reading. Meant for before training
+     * starts, whatever was learned so far is gone
+     */
+    pub fn set_sensors(&mut self, sensors: SensorSet) {
+        for bird in self.all_birds.iter_mut() {
+            bird.brain =
+                NeuralNetwork::new(sensors.size() as i32, HIDDEN_NODES, self.head.outputs());
+            bird.set_sensors(sensors.clone());
+        }
+        let standardizing = self.normalizer.standardizing();
+        self.normalizer = Normalizer::new(&sensors, &self.limits());
+        self.sensors = sensors;
+        if standardizing {
+            self.standardize();
+        }
+    }
+
+    /**
+     * Standardize inputs with the mean and standard deviation of
+     * everything birds saw in earlier generations, instead of
+     * mapping the declared ranges to 0..1
+     */
+    pub fn standardize(&mut self) {
+        self.normalizer.standardize();
+        for bird in self.all_birds.iter_mut() {
+            bird.normalizer = self.normalizer.fresh();
+        }
+    }
+
+    // Print min, max, mean and clamping of every input after a generation
+    pub fn show_input_stats(&mut self, input_stats: bool) {
+        self.input_stats = input_stats;
+    }
+
+    // Add what the birds of this generation saw to the statistics
+    fn update_normalizer(&mut self) {
+        for bird in self.all_birds.iter() {
+            self.normalizer.merge(&bird.normalizer);
+        }
+        self.normalizer.refresh();
+        if self.input_stats {
+            println!("Inputs after generation {}", self.generation);
+            print!("{}", self.normalizer.dump());
+        }
+    }
+
+    /**
+     * Gives every bird this action head and a new random brain with
+     * the outputs it needs. Like set_sensors, meant for before training
+     */
+    pub fn set_action_head(&mut self, head: ActionHead) {
+        for bird in self.all_birds.iter_mut() {
+            bird.brain =
+                NeuralNetwork::new(self.sensors.size() as i32, HIDDEN_NODES, head.outputs());
+            bird.head = head;
+        }
+        self.head = head;
+    }
+
+    // Gravity, flaps and size of every bird from now on
+    pub fn set_physics(&mut self, physics: PhysicsConfig) {
+        for bird in self.all_birds.iter_mut() {
+            bird.set_physics(physics);
+        }
+        self.physics = physics;
+        self.normalizer.set_limits(&self.sensors, &self.limits());
+    }
+
+    // Score every bird over several episodes before breeding
+    pub fn evaluate_with(&mut self, evaluation: Evaluation) {
+        self.evaluation = Some(evaluation);
+    }
+
+    /**
+     * Measures the generation that just ended. Returns the reaction
+     * once the best score went too long without improving, raising
+     * the mutation rate right away if that's the reaction
+     */
+    fn check_progress(&mut self) -> Option<Reaction> {
+        self.genotype_diversity = diversity::genotype(&self.all_birds);
+        self.behaviour_diversity = diversity::behaviour(&self.all_birds);
+        let best = self.all_birds.iter().map(|b| b.score).max().unwrap_or(0);
+        if best > self.best_score {
+            self.best_score = best;
+            self.stagnant = 0;
+            self.mutation_rate = MUTATION_RATE;
+            return None;
+        }
+        self.stagnant += 1;
+        let (patience, reaction) = self.stagnation?;
+        if self.stagnant < patience {
+            return None;
+        }
+        println!("No better score for {} generations", self.stagnant);
+        self.stagnant = 0;
+        match reaction {
+            Reaction::Mutate => {
+                self.mutation_rate = (self.mutation_rate * 2.0).min(MAX_MUTATION_RATE)
+            }
+            // The new birds need a chance to set their own best
+            Reaction::Restart => self.best_score = 0,
+            Reaction::Immigrants => {}
+        }
+        Some(reaction)
+    }
+
+    // A random bird starting a family of its own
+    fn founder(&mut self) -> Bird {
+        let brain = NeuralNetwork::new(
+            self.sensors.size() as i32,
+            HIDDEN_NODES,
+            self.head.outputs(),
+        );
+        let mut bird = Bird::new(self.width, self.height, brain);
+        bird.head = self.head;
+        bird.sensors = self.sensors.clone();
+        bird.set_pipe_config(self.generator.base);
+        bird.normalizer = self.normalizer.fresh();
+        bird.set_physics(self.physics);
+        bird.born = self.generation;
+        bird.lineage = self.next_lineage;
+        self.next_lineage += 1;
+        bird
+    }
+
+    // Log the generation that just ended and export what was asked for
+    fn update_genealogy(&mut self) {
+        for bird in self.all_birds.iter() {
+            self.genealogy.record(bird);
+        }
+        if let Some(best) = self.all_birds.iter().max_by_key(|b| b.score) {
+            if !matches!(self.champion, Some((_, score)) if score >= best.score) {
+                self.champion = Some((best.id, best.score));
+            }
+        }
+        if let Some(path) = &self.genealogy_out {
+            if let Err(e) = self.genealogy.save(path) {
+                println!("{}", e);
+            }
+        }
+        if let (Some(path), Some((id, _))) = (&self.ancestry_out, self.champion) {
+            if let Err(e) = self.genealogy.ancestry(id).save(path) {
+                println!("{}", e);
+            }
+        }
+    }
+
+    // The champion only belongs to this generation when it was just crowned
+    fn save_champion_brain(&self) {
+        let (path, (id, _)) = match (&self.brain_out, self.champion) {
+            (Some(path), Some(champion)) => (path, champion),
+            _ => return,
+        };
+        if let Some(bird) = self.all_birds.iter().find(|b| b.id == id) {
+            if let Err(e) = bird.brain.save(path) {
+                println!("{}", e);
+            }
+            // The brain only flies the same on the inputs it learned with
+            if bird.normalizer.standardizing() {
+                if let Err(e) = bird.normalizer.save(&normalize::stats_path(path)) {
+                    println!("{}", e);
                 }
-            } else {
-                let mut acc_birds: Vec<usize> = Vec::new();
-                let mut add_bird;
-                for i in 0..self.active_birds.len() {
-                    add_bird = true;
-                    let index = self.active_birds[i];
-                    let bird = &mut self.all_birds[index];
-                    bird.think(&new_pipes);
-                    bird.update();
-
-                    for j in 0..new_pipes.len() {
-                        if new_pipes[j].hits(bird) {
-                            add_bird = false;
-                            break;
-                        }
-                    }
-                    if !bird.bottom_top() && add_bird {
-                        acc_birds.push(index);
-                    }
+            }
+        }
+    }
+
+    // Keep the run of a bird that just died if it's the generation's longest
+    fn record_death(&mut self, index: usize) {
+        let flight = &self.flights[index];
+        let best = self.generation_best.as_ref().map_or(0, |r| r.flaps.len());
+        if self.record_dir.is_some() && flight.bird.flaps.len() > best {
+            self.generation_best = Some(Recording::of(flight));
+        }
+    }
+
+    pub fn cycle_speed(&self) -> i32 {
+        self.cycle_speed
+    }
+
+    // How many simulation steps run per frame
+    pub fn set_cycle_speed(&mut self, cycle_speed: i32) {
+        self.cycle_speed = cycle_speed.clamp(1, MAX_CYCLE_SPEED);
+    }
+
+    pub fn show_rays(&self) -> bool {
+        self.show_rays
+    }
+
+    pub fn set_show_rays(&mut self, show_rays: bool) {
+        self.show_rays = show_rays;
+    }
+
+    pub fn coloring(&self) -> Coloring {
+        self.coloring
+    }
+
+    pub fn set_coloring(&mut self, coloring: Coloring) {
+        self.coloring = coloring;
+    }
+
+    // Sends every bird of the population off on the course of this run
+    fn launch(&mut self) {
+        let birds = std::mem::take(&mut self.all_birds);
+        self.flights = birds
+            .into_iter()
+            .map(|bird| Flight::start(bird, &self.generator, self.seed))
+            .collect();
+        self.active_birds = (0..self.flights.len()).collect();
+    }
+
+    // Brings the birds back from their flights, scores and all
+    fn land(&mut self) {
+        self.all_birds = self.flights.drain(..).map(|flight| flight.bird).collect();
+        self.active_birds.clear();
+    }
+
+    // Advance the simulation one step, every bird on its own course
+    pub fn update(&mut self) {
+        if self.flights.is_empty() {
+            self.launch();
+        }
+        if self.run_best {
+            if let Some(best_index) = self.best_bird {
+                let flight = &mut self.flights[best_index];
+                flight.step(|bird, pipes| bird.think(pipes));
+                // Start over, bird crashed
+                if flight.outcome.is_some() {
+                    self.reset_game();
                 }
-                self.active_birds = acc_birds;
             }
-            if self.counter % 75 == 0 {
-                self.pipes.push(Pipe::new(self.width, self.height));
+        } else {
+            let mut acc_birds: Vec<usize> = Vec::new();
+            for index in std::mem::take(&mut self.active_birds) {
+                let flight = &mut self.flights[index];
+                flight.step(|bird, pipes| bird.think(pipes));
+                self.pipes_passed = self.pipes_passed.max(flight.pipes_passed);
+                if flight.outcome.is_none() {
+                    acc_birds.push(index);
+                } else {
+                    self.record_death(index);
+                }
             }
-            self.counter = self.counter + 1;
+            self.active_birds = acc_birds;
         }
+        self.counter += 1;
+
+        self.update_high_score();
+        // If we're out of birds go to the next generation
+        if !self.run_best && self.active_birds.is_empty() {
+            println!("Next generation");
+            self.next_generation();
+        }
+    }
+
+    /**
+     * The most promising bird flying right now. Everyone alive has
+     * the same score and passed the same pipes, so it's the child of
+     * the fittest parents, ties going to the first bird
+     */
+    fn leader(&self) -> Option<usize> {
+        if self.run_best {
+            return self.best_bird;
+        }
+        self.active_birds.iter().cloned().rev().max_by(|&a, &b| {
+            let (a, b) = (&self.flights[a].bird, &self.flights[b].bird);
+            a.parent_fitness.partial_cmp(&b.parent_fitness).unwrap()
+        })
+    }
+
+    // The leader's flight: highlighted, shown with its rays, and its pipes stand for everyone's
+    fn watched(&self) -> Option<&Flight> {
+        self.leader().map(|index| &self.flights[index])
+    }
+
+    fn update_high_score(&mut self) {
         let mut tmp_high_score: u32 = 0;
         if !self.run_best {
             // which is the best bird?
             let mut tmp_best_bird = None;
             for i in 0..self.active_birds.len() {
                 let index = self.active_birds[i];
-                let s = self.all_birds[index].score as u32;
+                let s = self.flights[index].bird.score as u32;
                 if s > tmp_high_score {
                     tmp_high_score = s;
                     tmp_best_bird = Some(index);
@@ -129,58 +603,169 @@ impl Game {
             }
         } else {
             // Just one bird, the best one so far
-            if self.best_bird.is_some() {
-                let best_index = self.best_bird.ok_or("No best bird")?;
-                let bb = &mut self.all_birds[best_index];
-                let tmp_high_score: u32 = bb.score as u32;
+            if let Some(best_index) = self.best_bird {
+                tmp_high_score = self.flights[best_index].bird.score as u32;
                 if tmp_high_score > self.high_score {
                     self.high_score = tmp_high_score;
                 }
             }
         }
-        println!("High score: {}", tmp_high_score);
+        self.current_score = tmp_high_score;
+    }
+
+    /**
+     * Draws the current state, alpha is how far we are
+     * between the last simulation step and the next one
+     */
+    pub fn draw(
+        &mut self,
+        canvas: &mut dyn DrawTarget,
+        theme: &Theme,
+        alpha: f32,
+    ) -> Result<(), String> {
+        let watched = self.watched();
+        theme.background(canvas, watched.map_or(0.0, |flight| flight.scrolled(alpha)))?;
+
+        println!("High score: {}", self.current_score);
         println!("All time high score: {}", self.high_score);
+        let pipes = watched.map_or(&[][..], |flight| &flight.pipes[..]);
         // Draw everything
-        self.pipes.iter().for_each(|pipe| {
-            pipe.show(canvas);
-        });
+        for pipe in pipes.iter() {
+            theme.pipe(canvas, pipe, alpha)?;
+        }
 
         if self.run_best && self.best_bird.is_some() {
             let best_index = self.best_bird.ok_or("No best bird")?;
-            self.all_birds[best_index].show(canvas);
+            theme.bird(canvas, &self.flights[best_index].bird, theme.bird, alpha)?;
+        } else if self.coloring == Coloring::Plain {
+            let birds = self
+                .active_birds
+                .iter()
+                .map(|&index| &self.flights[index].bird);
+            theme.birds(canvas, birds, theme.bird, alpha)?;
         } else {
-            for i in 0..self.active_birds.len() {
-                self.all_birds[self.active_birds[i]].show(canvas)
-            }
-            // If we're out of birds go to the next generation
-            if self.active_birds.len() == 0 {
-                println!("Next generation");
-                self.next_generation();
+            let colored = self.colored();
+            theme.colored_birds(canvas, &colored, alpha)?;
+            if let Some(flight) = watched {
+                theme.highlight(canvas, &flight.bird, alpha)?;
             }
+
+            let legend = match self.coloring {
+                Coloring::Lineage => {
+                    let families: HashSet<u32> = colored.iter().map(|(b, _)| b.lineage).collect();
+                    format!("LINEAGES {}", families.len())
+                }
+                _ => String::from("RANKED BY PARENT FITNESS"),
+            };
+            canvas.set_color(theme.text);
+            canvas.text(10, 10, 2, &legend)?;
+        }
+
+        if let (true, Some(flight)) = (self.show_rays, watched) {
+            let bird = &flight.bird;
+            // Birds without rays get shown what they would see
+            let rays = self.sensors.rays().unwrap_or(DEFAULT_RAYS);
+            canvas.set_color(theme.best);
+            vision::show(canvas, bird, pipes, rays, alpha)?;
         }
         Ok(())
     }
 
+    /**
+     * The birds still flying with their colors, the children of the
+     * fittest parents first. Everyone alive has the same score, so
+     * ranks go by the fitness of their parents
+     */
+    fn colored(&self) -> Vec<(&Bird, Color)> {
+        let mut ranked: Vec<&Bird> = self
+            .active_birds
+            .iter()
+            .map(|&index| &self.flights[index].bird)
+            .collect();
+        ranked.sort_by(|a, b| b.parent_fitness.partial_cmp(&a.parent_fitness).unwrap());
+        let last = (ranked.len().max(2) - 1) as f32;
+        ranked
+            .iter()
+            .enumerate()
+            .map(|(i, &bird)| match self.coloring {
+                Coloring::Lineage => (bird, lineage_color(bird.lineage)),
+                _ => (bird, rank_color(i as f32 / last)),
+            })
+            .collect()
+    }
+
     // Start the game over
     pub fn reset_game(&mut self) {
         self.counter = 0;
-        if self.best_bird.is_some() {
-            let best_index = self.best_bird.unwrap();
-            self.all_birds[best_index].score = 0;
-            self.best_bird = Some(best_index);
+        self.pipes_passed = 0;
+        self.started = Instant::now();
+        // Every run gets a fresh pipe course
+        self.seed = rand::random();
+        match (self.run_best, self.best_bird) {
+            // Just the best bird flies again
+            (true, Some(best_index)) => self.flights[best_index].restart(self.seed),
+            _ => self.launch(),
+        }
+    }
+
+    fn save_generation_best(&mut self) {
+        if let (Some(dir), Some(recording)) = (&self.record_dir, self.generation_best.take()) {
+            let path = format!("{}/gen-{}.rec", dir, self.generation);
+            if let Err(e) = recording.save(&path) {
+                println!("{}", e);
+            }
         }
-        self.pipes = Vec::new();
     }
 
     // Create the next generation
     pub fn next_generation(&mut self) {
+        self.land();
+        self.save_generation_best();
+        self.update_genealogy();
+        self.save_champion_brain();
+        self.update_normalizer();
+        self.frames += self.all_birds.iter().map(|b| b.score).sum::<u64>();
+        let reaction = self.check_progress();
+        self.log_stats();
+        if let Some(level) = self.generator.finish_generation(self.pipes_passed) {
+            println!("Curriculum level {}", level);
+        }
+        self.generation += 1;
         self.normalize_fitness();
-        self.active_birds = Game::generate(&self.all_birds);
-        self.reset_game();
-        for i in 0..self.active_birds.len() {
-            let index = self.active_birds[i];
-            self.all_birds[index] = self.all_birds[index].copy();
+        self.all_birds = self.breed();
+        for bird in self.all_birds.iter_mut() {
+            bird.normalizer = self.normalizer.fresh();
         }
+        match reaction {
+            Some(Reaction::Immigrants) => {
+                // Children come in random order, any of them can go
+                for i in self.all_birds.len() - IMMIGRANTS..self.all_birds.len() {
+                    self.all_birds[i] = self.founder();
+                }
+            }
+            Some(Reaction::Restart) => {
+                self.all_birds = (0..POPULATION).map(|_| self.founder()).collect();
+            }
+            _ => {}
+        }
+        // The old population is gone along with the best bird
+        self.best_bird = None;
+        self.reset_game();
+    }
+
+    /**
+     * A new population with a mutated child for every pick of pool
+     * selection. Picks used to point back into the old population,
+     * so a parent picked twice was mutated twice in place and then
+     * flew twice a frame under two indices, while birds nobody picked
+     * stayed around. Children of their own keep their own id, parents
+     * and lineage, and the population always has the same size
+     */
+    fn breed(&self) -> Vec<Bird> {
+        Game::generate(&self.all_birds)
+            .iter()
+            .map(|&index| self.all_birds[index].mutated(self.mutation_rate, MUTATION_SIZE))
+            .collect()
     }
 
     pub fn generate(all_birds: &Vec<Bird>) -> Vec<usize> {
@@ -210,22 +795,143 @@ impl Game {
 
     // Normalize the fitness of all birds
     pub fn normalize_fitness(&mut self) {
-        for i in 0..self.all_birds.len() {
-            let mut b = &mut self.all_birds[i];
-            let val = b.score;
-            b.score = val;
-        }
+        // The run everyone saw, or episodes nobody did
+        let raw: Vec<f32> = match self.evaluation {
+            Some(evaluation) => {
+                let (scores, flown) = evaluation.score_all(&self.all_birds, &self.generator);
+                self.frames += flown;
+                scores
+            }
+            None => self.all_birds.iter().map(|b| b.score as f32).collect(),
+        };
         // Add up all the scores
-        let sum = self
-            .all_birds
-            .iter()
-            .fold(0.0, |acc, e| acc + e.score as f32);
+        let sum: f32 = raw.iter().sum();
         if sum > 0.0 {
-            for i in 0..self.all_birds.len() {
-                let mut b = &mut self.all_birds[i];
-                let new_fitness = (b.score as f32) / sum;
-                b.fitness = new_fitness;
+            for (b, score) in self.all_birds.iter_mut().zip(raw.iter()) {
+                b.fitness = score / sum;
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leader_is_the_child_of_the_fittest_parents() {
+        let mut game = Game::new(800, 600);
+        game.launch();
+        game.flights[7].bird.parent_fitness = 0.3;
+        game.flights[3].bird.parent_fitness = 0.2;
+        assert_eq!(game.leader(), Some(7));
+        // The ring moves on once the leader crashes
+        game.active_birds.retain(|&index| index != 7);
+        assert_eq!(game.leader(), Some(3));
+        // Ties go to the first bird
+        game.flights[1].bird.parent_fitness = 0.2;
+        assert_eq!(game.leader(), Some(1));
+    }
+
+    #[test]
+    fn rays_and_pipes_follow_the_leader() {
+        let mut game = Game::new(800, 600);
+        game.launch();
+        game.update();
+        // Any bird that is still flying
+        let leader = *game.active_birds.last().unwrap();
+        game.flights[leader].bird.parent_fitness = 0.5;
+        let watched = game.watched().unwrap();
+        assert_eq!(watched.bird.id, game.flights[leader].bird.id);
+        // Everyone flies the same course, so its pipes are everyone's
+        assert_eq!(watched.pipes[0].x, game.flights[0].pipes[0].x);
+
+        game.run_best = true;
+        game.best_bird = Some(4);
+        assert_eq!(game.watched().unwrap().bird.id, game.flights[4].bird.id);
+    }
+
+    #[test]
+    fn breeds_one_child_per_pick() {
+        let mut game = Game::new(800, 600);
+        game.all_birds[3].fitness = 0.5;
+        game.all_birds[8].fitness = 0.5;
+        let parents = [game.all_birds[3].id, game.all_birds[8].id];
+        let children = game.breed();
+        assert_eq!(children.len(), POPULATION);
+        assert!(children
+            .iter()
+            .all(|child| child.parents.len() == 1 && parents.contains(&child.parents[0])));
+        // Every child is a bird of its own
+        let ids: HashSet<u64> = children.iter().map(|child| child.id).collect();
+        assert_eq!(ids.len(), POPULATION);
+        assert!(!ids.contains(&parents[0]) && !ids.contains(&parents[1]));
+    }
+
+    #[test]
+    fn colors_by_rank_and_lineage() {
+        let mut game = Game::new(800, 600);
+        game.launch();
+        game.flights[9].bird.parent_fitness = 0.4;
+        game.flights[2].bird.parent_fitness = 0.3;
+        game.flights[5].bird.parent_fitness = -1.0;
+        game.set_coloring(Coloring::Rank);
+        let colored: Vec<(u64, Color)> = game.colored().iter().map(|(b, c)| (b.id, *c)).collect();
+        assert_eq!(colored.len(), POPULATION);
+        assert_eq!(colored[0], (game.flights[9].bird.id, rank_color(0.0)));
+        assert_eq!(colored[1].0, game.flights[2].bird.id);
+        assert_eq!(
+            colored[POPULATION - 1],
+            (game.flights[5].bird.id, rank_color(1.0))
+        );
+
+        game.flights[2].bird.lineage = game.flights[9].bird.lineage;
+        game.set_coloring(Coloring::Lineage);
+        let colored = game.colored();
+        assert_eq!(colored[0].1, colored[1].1);
+        assert!(colored
+            .iter()
+            .all(|&(bird, color)| color == lineage_color(bird.lineage)));
+    }
+
+    #[test]
+    fn stagnation_raises_the_mutation_rate() {
+        let mut game = Game::new(800, 600);
+        // Fewer birds to measure the diversity of
+        game.all_birds.truncate(20);
+        game.on_stagnation(3, Reaction::Mutate);
+        // Nobody beats the best score of 0
+        assert_eq!(game.check_progress(), None);
+        assert_eq!(game.check_progress(), None);
+        assert_eq!(game.mutation_rate, MUTATION_RATE);
+        assert_eq!(game.check_progress(), Some(Reaction::Mutate));
+        assert_eq!(game.mutation_rate, MUTATION_RATE * 2.0);
+        for _ in 0..3 * 2 {
+            game.check_progress();
+        }
+        assert_eq!(game.mutation_rate, MAX_MUTATION_RATE);
+        // Progress calms it down again
+        game.all_birds[0].score = 10;
+        assert_eq!(game.check_progress(), None);
+        assert_eq!(game.mutation_rate, MUTATION_RATE);
+    }
+
+    #[test]
+    fn stagnation_brings_in_immigrants() {
+        let mut game = Game::new(800, 600);
+        game.on_stagnation(1, Reaction::Immigrants);
+        game.best_score = 10;
+        game.launch();
+        game.flights[0].bird.score = 1;
+        game.next_generation();
+        game.land();
+        assert_eq!(game.all_birds.len(), POPULATION);
+        // The last children make room for founders of new families
+        let (children, immigrants) = game.all_birds.split_at(POPULATION - IMMIGRANTS);
+        assert!(children.iter().all(|b| b.parents.len() == 1));
+        assert!(immigrants.iter().all(|b| b.parents.is_empty()));
+        let families: HashSet<u32> = immigrants.iter().map(|b| b.lineage).collect();
+        assert_eq!(families.len(), IMMIGRANTS);
+        assert!(families.iter().all(|&lineage| lineage >= POPULATION as u32));
+    }
+}
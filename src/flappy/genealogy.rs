@@ -0,0 +1,171 @@
+use crate::flappy::bird::Bird;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+
+// What is known about a bird once its run is over
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub id: u64,
+    pub parents: Vec<u64>,
+    pub generation: u32,
+    pub lineage: u32,
+    pub mutations: u32,
+    pub score: u64,
+}
+
+impl Record {
+    pub fn of(bird: &Bird) -> Record {
+        Record {
+            id: bird.id,
+            parents: bird.parents.clone(),
+            generation: bird.born,
+            lineage: bird.lineage,
+            mutations: bird.mutations,
+            score: bird.score,
+        }
+    }
+}
+
+/**
+ * Every bird of every generation and who it was bred from.
+ * Following the parents back from a champion shows which
+ * birds and mutations it took to get there
+ */
+#[derive(Debug, Clone, Default)]
+pub struct Genealogy {
+    records: Vec<Record>,
+    // Position of every id in records
+    index: HashMap<u64, usize>,
+}
+
+impl Genealogy {
+    pub fn new() -> Genealogy {
+        Genealogy::default()
+    }
+
+    // Adds the bird, or updates it if it's already known
+    pub fn record(&mut self, bird: &Bird) {
+        let record = Record::of(bird);
+        match self.index.get(&record.id) {
+            Some(&i) => self.records[i] = record,
+            None => {
+                self.index.insert(record.id, self.records.len());
+                self.records.push(record);
+            }
+        }
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Record> {
+        self.index.get(&id).map(|&i| &self.records[i])
+    }
+
+    // The bird with its parents, their parents and so on
+    pub fn ancestry(&self, id: u64) -> Genealogy {
+        let mut seen = HashSet::new();
+        let mut pending = vec![id];
+        while let Some(id) = pending.pop() {
+            if let Some(record) = self.get(id) {
+                if seen.insert(id) {
+                    pending.extend(record.parents.iter().cloned());
+                }
+            }
+        }
+        let mut ancestry = Genealogy::new();
+        for record in self.records.iter().filter(|r| seen.contains(&r.id)) {
+            ancestry.index.insert(record.id, ancestry.records.len());
+            ancestry.records.push(record.clone());
+        }
+        ancestry
+    }
+
+    // Graphviz graph, oldest generation on the left
+    pub fn to_dot(&self) -> String {
+        let mut generations: BTreeMap<u32, Vec<u64>> = BTreeMap::new();
+        for record in self.records.iter() {
+            generations
+                .entry(record.generation)
+                .or_default()
+                .push(record.id);
+        }
+        let mut dot = String::from("digraph genealogy {\n  rankdir=LR;\n  node [shape=box];\n");
+        for record in self.records.iter() {
+            dot.push_str(&format!(
+                "  b{} [label=\"#{} gen {}\\nscore {}\\n{} mutations\"];\n",
+                record.id, record.id, record.generation, record.score, record.mutations
+            ));
+        }
+        for ids in generations.values() {
+            let nodes: Vec<String> = ids.iter().map(|id| format!("b{}", id)).collect();
+            dot.push_str(&format!("  {{ rank=same; {}; }}\n", nodes.join("; ")));
+        }
+        for record in self.records.iter() {
+            for parent in record.parents.iter().filter(|p| self.index.contains_key(p)) {
+                dot.push_str(&format!("  b{} -> b{};\n", parent, record.id));
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    pub fn to_json(&self) -> String {
+        let rows: Vec<String> = self
+            .records
+            .iter()
+            .map(|r| {
+                let parents: Vec<String> = r.parents.iter().map(|p| p.to_string()).collect();
+                format!(
+                    "  {{\"id\": {}, \"parents\": [{}], \"generation\": {}, \"lineage\": {}, \"mutations\": {}, \"score\": {}}}",
+                    r.id,
+                    parents.join(", "),
+                    r.generation,
+                    r.lineage,
+                    r.mutations,
+                    r.score
+                )
+            })
+            .collect();
+        format!("[\n{}\n]\n", rows.join(",\n"))
+    }
+
+    // DOT or JSON, whichever the extension asks for
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let text = if path.ends_with(".dot") || path.ends_with(".gv") {
+            self.to_dot()
+        } else if path.ends_with(".json") {
+            self.to_json()
+        } else {
+            return Err(format!(
+                "Unknown genealogy format for {}, use .dot or .json",
+                path
+            ));
+        };
+        fs::write(path, text).map_err(|e| format!("Unable to write {}: {}", path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flappy::bird::{MUTATION_RATE, MUTATION_SIZE};
+    use crate::flappy::testing;
+
+    #[test]
+    fn ancestry_follows_parents_only() {
+        let founder = testing::bird();
+        let stranger = testing::bird();
+        let child = founder.mutated(MUTATION_RATE, MUTATION_SIZE);
+        let grandchild = child.mutated(MUTATION_RATE, MUTATION_SIZE);
+        let mut genealogy = Genealogy::new();
+        for bird in [&founder, &stranger, &child, &grandchild].iter() {
+            genealogy.record(bird);
+        }
+
+        let ancestry = genealogy.ancestry(grandchild.id);
+        let ids: Vec<u64> = ancestry.records.iter().map(|r| r.id).collect();
+        assert_eq!(ids, vec![founder.id, child.id, grandchild.id]);
+        assert_eq!(ancestry.get(grandchild.id).unwrap().generation, 2);
+        assert!(ancestry
+            .to_dot()
+            .contains(&format!("b{} -> b{}", child.id, grandchild.id)));
+    }
+}
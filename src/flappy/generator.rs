@@ -0,0 +1,196 @@
+use crate::flappy::pipe::{Pipe, PipeConfig};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+// When the curriculum makes things harder
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pace {
+    // Once a whole generation got far enough
+    Generations,
+    // During a run, with every few pipes passed
+    Score,
+}
+
+/**
+ * Makes the pipes harder as the birds get better, smaller
+ * gaps and faster pipes every level. Without it the course
+ * never changes and the brains stop improving once they
+ * can handle it
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Curriculum {
+    pub pace: Pace,
+    // Pipes to pass to reach the next level
+    pub pipes_per_level: u32,
+    // Gap lost and speed gained per level
+    pub gap_step: f32,
+    pub speed_step: f32,
+    // Hardest it gets
+    pub min_gap: f32,
+    pub max_speed: f32,
+}
+
+impl Curriculum {
+    pub fn new(pace: Pace) -> Curriculum {
+        Curriculum {
+            pace,
+            pipes_per_level: 10,
+            gap_step: 5.0,
+            speed_step: 0.5,
+            min_gap: 70.0,
+            max_speed: 12.0,
+        }
+    }
+
+    pub fn parse(name: &str) -> Result<Curriculum, String> {
+        match name {
+            "generations" => Ok(Curriculum::new(Pace::Generations)),
+            "score" => Ok(Curriculum::new(Pace::Score)),
+            _ => Err(format!(
+                "Unknown curriculum {}, use generations or score",
+                name
+            )),
+        }
+    }
+
+    // The base pipes made harder for the level
+    pub fn apply(&self, base: &PipeConfig, level: u32) -> PipeConfig {
+        let level = level as f32;
+        PipeConfig {
+            gap: (base.gap - level * self.gap_step).max(self.min_gap.min(base.gap)),
+            speed: (base.speed + level * self.speed_step).min(self.max_speed.max(base.speed)),
+            ..*base
+        }
+    }
+}
+
+/**
+ * Spawns the pipes of a course. The same seed and config give
+ * the same course, that's what recordings and benchmarks rely on
+ */
+#[derive(Debug, Clone)]
+pub struct PipeGenerator {
+    width: u32,
+    height: u32,
+    pub base: PipeConfig,
+    curriculum: Option<Curriculum>,
+    // Reached by earlier generations, see Pace::Generations
+    level: u32,
+    rng: StdRng,
+    // Frame the next pipe is due
+    next_spawn: u32,
+}
+
+impl PipeGenerator {
+    pub fn new(width: u32, height: u32, seed: u64, base: PipeConfig) -> PipeGenerator {
+        PipeGenerator {
+            width,
+            height,
+            base,
+            curriculum: None,
+            level: 0,
+            rng: StdRng::seed_from_u64(seed),
+            next_spawn: 0,
+        }
+    }
+
+    pub fn set_curriculum(&mut self, curriculum: Option<Curriculum>) {
+        self.curriculum = curriculum;
+        self.level = 0;
+    }
+
+    // Start a new course, the level reached stays
+    pub fn restart(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+        self.next_spawn = 0;
+    }
+
+    // Difficulty for a bird that passed pipes_passed pipes in this run
+    pub fn level(&self, pipes_passed: u32) -> u32 {
+        match self.curriculum {
+            Some(c) if c.pace == Pace::Score => pipes_passed / c.pipes_per_level.max(1),
+            Some(_) => self.level,
+            None => 0,
+        }
+    }
+
+    pub fn config(&self, pipes_passed: u32) -> PipeConfig {
+        match self.curriculum {
+            Some(c) => c.apply(&self.base, self.level(pipes_passed)),
+            None => self.base,
+        }
+    }
+
+    // Moves up a level once a generation's best passed enough pipes, returns the new level
+    pub fn finish_generation(&mut self, pipes_passed: u32) -> Option<u32> {
+        let c = self.curriculum?;
+        if c.pace == Pace::Generations && pipes_passed >= c.pipes_per_level {
+            self.level += 1;
+            return Some(self.level);
+        }
+        None
+    }
+
+    // The pipe due at frame, if any
+    pub fn spawn(&mut self, frame: u32, pipes_passed: u32) -> Option<Pipe> {
+        if frame < self.next_spawn {
+            return None;
+        }
+        let mut config = self.config(pipes_passed);
+        // Leave room for the gap to be anywhere
+        config.gap = config.gap.min(self.height as f32 / 2.0 - 1.0);
+        self.next_spawn = frame + config.interval.max(1);
+        Some(Pipe::random(
+            self.width,
+            self.height,
+            &config,
+            &mut self.rng,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn curriculum_tightens_gaps_up_to_a_limit() {
+        let mut generator = PipeGenerator::new(800, 600, 1, PipeConfig::default());
+        generator.set_curriculum(Some(Curriculum::new(Pace::Score)));
+        assert_eq!(generator.config(9), PipeConfig::default());
+        let harder = generator.config(20);
+        assert_eq!(harder.gap, 115.0);
+        assert_eq!(harder.speed, 7.0);
+        let hardest = generator.config(10_000);
+        assert_eq!((hardest.gap, hardest.speed), (70.0, 12.0));
+    }
+
+    #[test]
+    fn levels_up_after_good_generations() {
+        let mut generator = PipeGenerator::new(800, 600, 1, PipeConfig::default());
+        assert_eq!(generator.finish_generation(100), None);
+        let curriculum = Curriculum::new(Pace::Generations);
+        generator.set_curriculum(Some(curriculum));
+        assert_eq!(
+            generator.finish_generation(curriculum.pipes_per_level - 1),
+            None
+        );
+        assert_eq!(
+            generator.finish_generation(curriculum.pipes_per_level),
+            Some(1)
+        );
+        assert_eq!(
+            generator.finish_generation(curriculum.pipes_per_level),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn spawns_every_interval() {
+        let mut generator = PipeGenerator::new(800, 600, 1, PipeConfig::default());
+        let frames: Vec<u32> = (0..200)
+            .filter(|&frame| generator.spawn(frame, 0).is_some())
+            .collect();
+        assert_eq!(frames, vec![0, 75, 150]);
+    }
+}
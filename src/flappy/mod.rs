@@ -1,4 +1,24 @@
 pub mod bird;
 pub mod pipe;
 
+pub mod action;
+pub mod benchmark;
+pub mod capture;
+pub mod diversity;
+pub mod dqn;
+pub mod env;
+pub mod episode;
 pub mod game;
+pub mod genealogy;
+pub mod generator;
+pub mod normalize;
+pub mod physics;
+pub mod play;
+pub mod recording;
+pub mod reinforce;
+pub mod sensors;
+pub mod stats;
+#[cfg(test)]
+pub mod testing;
+pub mod theme;
+pub mod vision;
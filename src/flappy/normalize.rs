@@ -0,0 +1,336 @@
+use crate::flappy::sensors::{Limits, SensorSet};
+use std::fs;
+
+// Standardized inputs beyond this many standard deviations are clamped
+const MAX_DEVIATIONS: f32 = 3.0;
+const HEADER: &str = "rusty-birds inputs v1";
+
+// Where the input statistics of the brain saved at path go
+pub fn stats_path(brain: &str) -> String {
+    format!("{}.inputs", brain)
+}
+
+/**
+ * Count, mean and variance of one input, updated one value at
+ * a time (Welford) and mergeable so every bird can keep its own
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Running {
+    pub count: u64,
+    pub mean: f64,
+    // Sum of squared differences from the mean
+    m2: f64,
+    pub min: f32,
+    pub max: f32,
+    // Values outside the declared range
+    pub clamped: u64,
+}
+
+impl Default for Running {
+    fn default() -> Running {
+        Running {
+            count: 0,
+            mean: 0.0,
+            m2: 0.0,
+            min: f32::INFINITY,
+            max: f32::NEG_INFINITY,
+            clamped: 0,
+        }
+    }
+}
+
+impl Running {
+    pub fn push(&mut self, x: f32) {
+        self.count += 1;
+        let delta = x as f64 - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (x as f64 - self.mean);
+        self.min = self.min.min(x);
+        self.max = self.max.max(x);
+    }
+
+    pub fn merge(&mut self, other: &Running) {
+        if other.count == 0 {
+            return;
+        }
+        let count = self.count + other.count;
+        let delta = other.mean - self.mean;
+        self.mean += delta * other.count as f64 / count as f64;
+        self.m2 += other.m2 + delta * delta * (self.count * other.count) as f64 / count as f64;
+        self.count = count;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.clamped += other.clamped;
+    }
+
+    pub fn variance(&self) -> f64 {
+        if self.count < 2 {
+            return 0.0;
+        }
+        self.m2 / self.count as f64
+    }
+
+    pub fn stddev(&self) -> f64 {
+        self.variance().sqrt()
+    }
+}
+
+/**
+ * Turns raw sensor readings into network inputs. By default every
+ * input is mapped from its declared range to 0..1 and clamped, so
+ * nothing saturates the sigmoid. Standardizing subtracts the mean
+ * and divides by the standard deviation seen so far instead
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Normalizer {
+    names: Vec<String>,
+    ranges: Vec<(f32, f32)>,
+    // Mean and standard deviation of every input when standardizing
+    standard: Option<Vec<(f32, f32)>>,
+    // What went in since the last reset
+    seen: Vec<Running>,
+}
+
+impl Normalizer {
+    pub fn new(sensors: &SensorSet, limits: &Limits) -> Normalizer {
+        let ranges = sensors.ranges(limits);
+        Normalizer {
+            names: sensors.names(),
+            seen: vec![Running::default(); ranges.len()],
+            ranges,
+            standard: None,
+        }
+    }
+
+    // Declared ranges for other limits, keeping the statistics
+    pub fn set_limits(&mut self, sensors: &SensorSet, limits: &Limits) {
+        self.ranges = sensors.ranges(limits);
+    }
+
+    pub fn standardizing(&self) -> bool {
+        self.standard.is_some()
+    }
+
+    /**
+     * Starts standardizing. Until real statistics come in, every
+     * input is taken to be spread evenly over its range
+     */
+    pub fn standardize(&mut self) {
+        let uniform = self
+            .ranges
+            .iter()
+            .map(|&(min, max)| ((min + max) / 2.0, (max - min) / 12.0_f32.sqrt()))
+            .collect();
+        self.standard = Some(uniform);
+    }
+
+    pub fn normalize(&self, raw: &[f32]) -> Vec<f32> {
+        match &self.standard {
+            Some(standard) => raw
+                .iter()
+                .zip(standard.iter())
+                .map(|(&x, &(mean, stddev))| {
+                    let z = (x - mean) / stddev.max(f32::EPSILON);
+                    z.clamp(-MAX_DEVIATIONS, MAX_DEVIATIONS)
+                })
+                .collect(),
+            None => raw
+                .iter()
+                .zip(self.ranges.iter())
+                .map(|(&x, &(min, max))| ((x - min) / (max - min)).clamp(0.0, 1.0))
+                .collect(),
+        }
+    }
+
+    /**
+     * Mean and standard deviation of every input, so a brain trained
+     * on standardized inputs can be flown later on what it learned with
+     */
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let standard = self
+            .standard
+            .as_ref()
+            .ok_or("Only standardized inputs have statistics to save")?;
+        let mut out = format!("{}\n", HEADER);
+        for (name, (mean, stddev)) in self.names.iter().zip(standard.iter()) {
+            out.push_str(&format!("{} {} {}\n", name, mean, stddev));
+        }
+        fs::write(path, out).map_err(|e| format!("Unable to write {}: {}", path, e))
+    }
+
+    // Standardize with statistics saved for the same sensors
+    pub fn load(&mut self, path: &str) -> Result<(), String> {
+        let text =
+            fs::read_to_string(path).map_err(|e| format!("Unable to read {}: {}", path, e))?;
+        let mut lines = text.lines();
+        if lines.next() != Some(HEADER) {
+            return Err(format!("{} is not input statistics", path));
+        }
+        let bad = |line: &str| format!("Malformed line in {}: {}", path, line);
+        let other = || format!("{} was saved for other sensors", path);
+
+        let mut standard = Vec::new();
+        for line in lines {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                [name, mean, stddev] => {
+                    if self.names.get(standard.len()).map(|n| n.as_str()) != Some(*name) {
+                        return Err(other());
+                    }
+                    let mean = mean.parse().map_err(|_| bad(line))?;
+                    let stddev = stddev.parse().map_err(|_| bad(line))?;
+                    standard.push((mean, stddev));
+                }
+                [] => {}
+                _ => return Err(bad(line)),
+            }
+        }
+        if standard.len() != self.names.len() {
+            return Err(other());
+        }
+        self.standard = Some(standard);
+        Ok(())
+    }
+
+    // Range every input ends up in
+    pub fn bounds(&self) -> Vec<(f32, f32)> {
+        match self.standard {
+            Some(_) => vec![(-MAX_DEVIATIONS, MAX_DEVIATIONS); self.ranges.len()],
+            None => vec![(0.0, 1.0); self.ranges.len()],
+        }
+    }
+
+    // Adds raw readings to the statistics
+    pub fn observe(&mut self, raw: &[f32]) {
+        for ((running, &x), &(min, max)) in self.seen.iter_mut().zip(raw).zip(self.ranges.iter()) {
+            running.push(x);
+            if x < min || x > max {
+                running.clamped += 1;
+            }
+        }
+    }
+
+    // Adds what another normalizer of the same sensors saw
+    pub fn merge(&mut self, other: &Normalizer) {
+        for (running, theirs) in self.seen.iter_mut().zip(other.seen.iter()) {
+            running.merge(theirs);
+        }
+    }
+
+    // Standardize with the statistics seen so far from now on
+    pub fn refresh(&mut self) {
+        if let Some(standard) = &mut self.standard {
+            for (params, running) in standard.iter_mut().zip(self.seen.iter()) {
+                if running.count > 1 && running.stddev() > 0.0 {
+                    *params = (running.mean as f32, running.stddev() as f32);
+                }
+            }
+        }
+    }
+
+    // The same normalization without any statistics, for a new bird
+    pub fn fresh(&self) -> Normalizer {
+        Normalizer {
+            seen: vec![Running::default(); self.seen.len()],
+            ..self.clone()
+        }
+    }
+
+    // Table of what every input looked like, to spot bad ranges
+    pub fn dump(&self) -> String {
+        let mut out = format!(
+            "{:<20} {:>9} {:>9} {:>9} {:>9} {:>9} {:>9} {:>8}\n",
+            "input", "from", "to", "min", "max", "mean", "stddev", "clamped"
+        );
+        for ((name, &(from, to)), running) in self
+            .names
+            .iter()
+            .zip(self.ranges.iter())
+            .zip(self.seen.iter())
+        {
+            let clamped = running.clamped as f64 * 100.0 / running.count.max(1) as f64;
+            out.push_str(&format!(
+                "{:<20} {:>9.2} {:>9.2} {:>9.2} {:>9.2} {:>9.2} {:>9.2} {:>7.1}%\n",
+                name,
+                from,
+                to,
+                running.min,
+                running.max,
+                running.mean,
+                running.stddev(),
+                clamped
+            ));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flappy::physics::PhysicsConfig;
+    use crate::flappy::pipe::PipeConfig;
+
+    fn of(sensors: &str) -> Normalizer {
+        let limits = Limits::new(800, 600, &PhysicsConfig::default(), &PipeConfig::default());
+        Normalizer::new(&SensorSet::parse(sensors).unwrap(), &limits)
+    }
+
+    #[test]
+    fn merged_statistics_match_one_pass() {
+        let values = [1.0, 4.0, 2.0, 8.0, 5.0, 7.0];
+        let mut all = Running::default();
+        values.iter().for_each(|&x| all.push(x));
+        let (mut a, mut b) = (Running::default(), Running::default());
+        values[..2].iter().for_each(|&x| a.push(x));
+        values[2..].iter().for_each(|&x| b.push(x));
+        a.merge(&b);
+        assert_eq!(a.count, 6);
+        assert!((a.mean - 4.5).abs() < 1e-9);
+        assert!((a.variance() - all.variance()).abs() < 1e-9);
+        assert_eq!((a.min, a.max), (1.0, 8.0));
+    }
+
+    #[test]
+    fn maps_and_clamps_to_the_declared_range() {
+        let normalizer = of("pipe,bird");
+        // Pipe 400 ahead, gap from 100 to 300, bird at 300 falling fast
+        let inputs = normalizer.normalize(&[400.0, 100.0, 300.0, 300.0, 60.0]);
+        assert_eq!(inputs, vec![0.5, 1.0 / 6.0, 0.5, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn standardizes_with_what_it_saw() {
+        let mut normalizer = of("pipe,bird");
+        normalizer.standardize();
+        normalizer.observe(&[100.0, 100.0, 300.0, 200.0, -4.0]);
+        normalizer.observe(&[300.0, 100.0, 300.0, 400.0, 4.0]);
+        normalizer.refresh();
+        let z = normalizer.normalize(&[300.0, 100.0, 300.0, 300.0, 40.0]);
+        assert_eq!(z[0], 1.0);
+        assert_eq!(z[3], 0.0);
+        assert_eq!(z[4], MAX_DEVIATIONS);
+        // Inputs that never changed keep the assumed spread
+        assert!(z[1].abs() < 3.0);
+    }
+
+    #[test]
+    fn saves_and_loads_statistics() {
+        let path = std::env::temp_dir().join("rusty-birds-test.inputs");
+        let path = path.to_str().unwrap();
+        let mut normalizer = of("pipe,bird");
+        assert!(normalizer.save(path).is_err());
+        normalizer.standardize();
+        normalizer.observe(&[100.0, 100.0, 300.0, 200.0, -4.0]);
+        normalizer.observe(&[300.0, 100.0, 300.0, 400.0, 4.0]);
+        normalizer.refresh();
+        normalizer.save(path).unwrap();
+        let mut loaded = of("pipe,bird");
+        loaded.load(path).unwrap();
+        let raw = [250.0, 120.0, 280.0, 310.0, 2.0];
+        assert_eq!(loaded.normalize(&raw), normalizer.normalize(&raw));
+        let mut other = of("pipe,bird,gap");
+        assert!(other.load(path).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
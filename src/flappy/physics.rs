@@ -0,0 +1,214 @@
+// How a flap changes the velocity of a bird
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Impulse {
+    // Lift is added, flapping again while rising goes up even faster
+    Add,
+    // Velocity becomes lift, like in the original game
+    Set,
+}
+
+impl Impulse {
+    pub fn parse(name: &str) -> Result<Impulse, String> {
+        match name {
+            "add" => Ok(Impulse::Add),
+            "set" => Ok(Impulse::Set),
+            _ => Err(format!("Unknown impulse {}, use add or set", name)),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Impulse::Add => "add",
+            Impulse::Set => "set",
+        }
+    }
+}
+
+/**
+ * How birds fall and flap, per frame. Every bird of a run
+ * has the same physics, brains only learn to fly in one
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PhysicsConfig {
+    // Added to the velocity every frame
+    pub gravity: f32,
+    // Velocity a flap gives, negative is up
+    pub lift: f32,
+    pub impulse: Impulse,
+    // Fastest a bird falls, infinity for no limit
+    pub terminal_velocity: f32,
+    // Fraction of the velocity lost to the air every frame
+    pub drag: f32,
+    // Frames after a flap before the next one does anything
+    pub cooldown: u32,
+    pub radius: f32,
+}
+
+impl Default for PhysicsConfig {
+    // The physics birds always had
+    fn default() -> PhysicsConfig {
+        PhysicsConfig {
+            gravity: 0.8,
+            lift: -12.0,
+            impulse: Impulse::Add,
+            terminal_velocity: f32::INFINITY,
+            drag: 0.0,
+            cooldown: 0,
+            radius: 12.0,
+        }
+    }
+}
+
+impl PhysicsConfig {
+    // Flaps set the velocity and falling speed is capped, like Flappy Bird
+    pub fn classic() -> PhysicsConfig {
+        PhysicsConfig {
+            gravity: 0.5,
+            lift: -8.5,
+            impulse: Impulse::Set,
+            terminal_velocity: 10.0,
+            ..PhysicsConfig::default()
+        }
+    }
+
+    // Weak gravity and thick air, slow to react in both directions
+    pub fn floaty() -> PhysicsConfig {
+        PhysicsConfig {
+            gravity: 0.25,
+            lift: -5.0,
+            impulse: Impulse::Set,
+            terminal_velocity: 5.0,
+            drag: 0.02,
+            ..PhysicsConfig::default()
+        }
+    }
+
+    // A big bird that drops like a stone and needs a moment between flaps
+    pub fn heavy() -> PhysicsConfig {
+        PhysicsConfig {
+            gravity: 1.2,
+            lift: -14.0,
+            impulse: Impulse::Set,
+            terminal_velocity: 16.0,
+            cooldown: 6,
+            radius: 14.0,
+            ..PhysicsConfig::default()
+        }
+    }
+
+    pub fn parse(preset: &str) -> Result<PhysicsConfig, String> {
+        match preset {
+            "original" => Ok(PhysicsConfig::default()),
+            "classic" => Ok(PhysicsConfig::classic()),
+            "floaty" => Ok(PhysicsConfig::floaty()),
+            "heavy" => Ok(PhysicsConfig::heavy()),
+            _ => Err(format!(
+                "Unknown physics {}, use original, classic, floaty or heavy",
+                preset
+            )),
+        }
+    }
+
+    // Velocity right after a flap, strength 1 is a full flap
+    pub fn flap(&self, velocity: f32, strength: f32) -> f32 {
+        match self.impulse {
+            Impulse::Add => velocity + self.lift * strength,
+            Impulse::Set => self.lift * strength,
+        }
+    }
+
+    /**
+     * Fastest a bird moves, two full flaps in a row up or a fall
+     * from the middle of a screen this high down
+     */
+    pub fn top_speed(&self, height: f32) -> f32 {
+        let up = match self.impulse {
+            Impulse::Add => 2.0 * self.lift.abs(),
+            Impulse::Set => self.lift.abs(),
+        };
+        let down = (self.gravity * height).sqrt().min(self.terminal_velocity);
+        up.max(down)
+    }
+
+    // Velocity one frame later
+    pub fn fall(&self, velocity: f32) -> f32 {
+        let velocity = (velocity + self.gravity) * (1.0 - self.drag);
+        velocity.min(self.terminal_velocity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flappy::bird::Bird;
+    use crate::flappy::testing;
+
+    fn bird(physics: PhysicsConfig) -> Bird {
+        let mut bird = testing::bird();
+        bird.set_physics(physics);
+        bird
+    }
+
+    #[test]
+    fn falls_like_it_always_did() {
+        let mut bird = bird(PhysicsConfig::default());
+        for _ in 0..10 {
+            bird.update();
+        }
+        // 0.8 * (1 + 2 + ... + 10) below the middle
+        assert!((bird.y - (300.0 + 0.8 * 55.0)).abs() < 0.001);
+        assert!((bird.velocity - 8.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn flaps_add_or_set_the_velocity() {
+        let mut rocket = bird(PhysicsConfig::default());
+        rocket.up();
+        rocket.up();
+        assert_eq!(rocket.velocity, -24.0);
+
+        let mut flappy = bird(PhysicsConfig::classic());
+        flappy.up();
+        flappy.up();
+        assert_eq!(flappy.velocity, -8.5);
+        flappy.update();
+        assert_eq!(flappy.velocity, -8.0);
+        assert_eq!(flappy.y, 292.0);
+    }
+
+    #[test]
+    fn falling_speed_is_capped() {
+        let mut bird = bird(PhysicsConfig::classic());
+        let mut fastest: f32 = 0.0;
+        for _ in 0..100 {
+            bird.update();
+            fastest = fastest.max(bird.velocity);
+        }
+        assert_eq!(fastest, 10.0);
+        assert_eq!(PhysicsConfig::classic().top_speed(600.0), 10.0);
+        // Two flaps up with the original physics
+        assert_eq!(PhysicsConfig::default().top_speed(600.0), 24.0);
+
+        // Drag slows a fast bird down even before the cap
+        let floaty = PhysicsConfig::floaty();
+        assert!(floaty.fall(-5.0) > -5.0 + floaty.gravity);
+    }
+
+    #[test]
+    fn flaps_wait_for_the_cooldown() {
+        let mut bird = bird(PhysicsConfig::heavy());
+        assert_eq!(bird.r, 14.0);
+        let mut flaps = 0;
+        for _ in 0..20 {
+            let before = bird.velocity;
+            bird.up();
+            if bird.velocity != before {
+                flaps += 1;
+            }
+            bird.update();
+        }
+        // Frames 0, 6, 12 and 18
+        assert_eq!(flaps, 4);
+        assert_eq!(bird.flaps.iter().filter(|&&f| f).count(), 4);
+    }
+}
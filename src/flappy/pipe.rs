@@ -1,32 +1,234 @@
 #[allow(dead_code)]
 #[allow(unused_variables)]
 use crate::flappy::bird::Bird;
-use crate::renderer::renderer::Renderer;
+use crate::renderer::target::DrawTarget;
 use rand::Rng;
+use std::f32::consts::PI;
 
-use sdl2::render::Canvas;
-use sdl2::video::Window;
+// How big is the empty space
+const SPACING: f32 = 125.0;
+// Pixels pipes move left every frame
+pub const SPEED: f32 = 6.0;
+const WIDTH: i32 = 80;
+// Frames between two pipes
+pub const INTERVAL: u32 = 75;
+// Furthest an oscillating gap moves from where it spawned
+const MAX_AMPLITUDE: f32 = 80.0;
+// Room between the two pairs of a staggered pipe
+const STAGGER: i32 = 100;
+// Smallest a closing gap gets, twice the size of a bird
+const MIN_GAP: f32 = 50.0;
+
+// Shape and pace of the pipes, the classic game by default
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PipeConfig {
+    // Height of the empty space
+    pub gap: f32,
+    pub speed: f32,
+    pub width: i32,
+    pub interval: u32,
+    pub variants: Variants,
+}
+
+impl Default for PipeConfig {
+    fn default() -> PipeConfig {
+        PipeConfig {
+            gap: SPACING,
+            speed: SPEED,
+            width: WIDTH,
+            interval: INTERVAL,
+            variants: Variants::default(),
+        }
+    }
+}
+
+/**
+ * Does the circle at (cx, cy) with radius r overlap the rectangle
+ * with its top left corner at (x, y)? The point of the rectangle
+ * closest to the center must be inside the circle
+ */
+pub fn circle_hits_rect(cx: f32, cy: f32, r: f32, x: f32, y: f32, w: f32, h: f32) -> bool {
+    let closest_x = cx.max(x).min(x + w);
+    let closest_y = cy.max(y).min(y + h);
+    let dx = cx - closest_x;
+    let dy = cy - closest_y;
+    dx * dx + dy * dy < r * r
+}
+
+/**
+ * How far along the ray from origin in direction dir it enters
+ * the rectangle with its top left corner at (x, y), in lengths
+ * of dir. None if it misses or the rectangle is behind
+ */
+pub fn ray_hits_rect(
+    origin: (f32, f32),
+    dir: (f32, f32),
+    x: f32,
+    y: f32,
+    w: f32,
+    h: f32,
+) -> Option<f32> {
+    let ((ox, oy), (dx, dy)) = (origin, dir);
+    let mut near = 0.0_f32;
+    let mut far = f32::INFINITY;
+    for &(o, d, min, max) in [(ox, dx, x, x + w), (oy, dy, y, y + h)].iter() {
+        if d == 0.0 {
+            // Parallel to this side, inside the slab or never
+            if o < min || o > max {
+                return None;
+            }
+            continue;
+        }
+        let (a, b) = ((min - o) / d, (max - o) / d);
+        near = near.max(a.min(b));
+        far = far.min(a.max(b));
+    }
+    if near <= far {
+        Some(near)
+    } else {
+        None
+    }
+}
+
+// What a pipe does besides scrolling left
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Variant {
+    Static,
+    // The gap moves up and down by amplitude around where it spawned
+    Oscillating { amplitude: f32, period: u32 },
+    // The gap shrinks to min_gap and opens up again
+    Closing { min_gap: f32, period: u32 },
+    // A second pair offset pixels behind, with its gap centered elsewhere
+    Staggered { offset: f32, center: f32 },
+}
+
+// Which variants the generator mixes in with the static pipes
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Variants {
+    pub oscillating: bool,
+    pub closing: bool,
+    pub staggered: bool,
+    // Any pipe can be from half to twice as wide
+    pub wide: bool,
+}
+
+impl Variants {
+    // A comma separated list like oscillating,wide
+    pub fn parse(list: &str) -> Result<Variants, String> {
+        let mut variants = Variants::default();
+        for name in list.split(',') {
+            match name {
+                "oscillating" => variants.oscillating = true,
+                "closing" => variants.closing = true,
+                "staggered" => variants.staggered = true,
+                "wide" => variants.wide = true,
+                _ => {
+                    return Err(format!(
+                        "Unknown pipe variant {}, use oscillating, closing, staggered or wide",
+                        name
+                    ))
+                }
+            }
+        }
+        Ok(variants)
+    }
+}
+
+// One top and bottom pair, staggered pipes have two
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Column {
+    pub x: f32,
+    pub prev_x: f32,
+    pub top: f32,
+    pub bottom: f32,
+}
 
 #[derive(Debug, Clone)]
 pub struct Pipe {
     pub x: f32,
+    // Position before the last update, for drawing in between steps
+    pub prev_x: f32,
     pub top: f32,
     pub bottom: f32,
     pub w: i32,
     pub speed: f32,
+    pub variant: Variant,
 
     width: u32,
     height: u32,
+    // Gap the pipe spawned with, moving pipes change around it
+    center_y: f32,
+    spacing: f32,
+    // Updates since it spawned
+    age: u32,
 }
 
 impl Pipe {
-    pub fn new(width: u32, height: u32) -> Pipe {
-        let f_height = height as f32;
-        let mut rng = rand::thread_rng();
-        // How big is the empty space
-        let spacing = 125.0;
+    pub fn random<R: Rng>(width: u32, height: u32, config: &PipeConfig, rng: &mut R) -> Pipe {
         // Where is the center of the empty space
-        let center_y = rng.gen_range(spacing, f_height - spacing);
+        let center_y = rng.gen_range(config.gap, height as f32 - config.gap);
+        let mut config = *config;
+        if config.variants.wide {
+            config.width = rng.gen_range(config.width / 2, config.width * 2 + 1);
+        }
+        let variant = Pipe::random_variant(height, center_y, &config, rng);
+        Pipe::shaped(width, height, center_y, &config, variant)
+    }
+
+    // Static pipes don't take anything from rng, so classic courses stay the same
+    fn random_variant<R: Rng>(
+        height: u32,
+        center_y: f32,
+        config: &PipeConfig,
+        rng: &mut R,
+    ) -> Variant {
+        let mut kinds = vec!["static"];
+        if config.variants.oscillating {
+            kinds.push("oscillating");
+        }
+        if config.variants.closing {
+            kinds.push("closing");
+        }
+        if config.variants.staggered {
+            kinds.push("staggered");
+        }
+        if kinds.len() == 1 {
+            return Variant::Static;
+        }
+        let (gap, f_height) = (config.gap, height as f32);
+        match kinds[rng.gen_range(0, kinds.len())] {
+            "oscillating" => Variant::Oscillating {
+                // Never moving the gap off screen
+                amplitude: (center_y - gap / 2.0)
+                    .min(f_height - center_y - gap / 2.0)
+                    .min(MAX_AMPLITUDE),
+                period: rng.gen_range(60, 150),
+            },
+            "closing" => Variant::Closing {
+                min_gap: (gap * 0.6).max(MIN_GAP).min(gap),
+                period: rng.gen_range(90, 180),
+            },
+            "staggered" => {
+                let shift = rng.gen_range(60.0, 150.0);
+                let shift = if rng.gen() { shift } else { -shift };
+                Variant::Staggered {
+                    offset: (config.width + STAGGER) as f32,
+                    center: (center_y + shift).max(gap).min(f_height - gap),
+                }
+            }
+            _ => Variant::Static,
+        }
+    }
+
+    pub fn shaped(
+        width: u32,
+        height: u32,
+        center_y: f32,
+        config: &PipeConfig,
+        variant: Variant,
+    ) -> Pipe {
+        let f_height = height as f32;
+        let spacing = config.gap;
 
         Pipe {
             // Top and bottom of pipe
@@ -34,50 +236,282 @@ impl Pipe {
             bottom: f_height - (center_y + spacing / 2.0),
             // Starts at the edge
             x: width as f32,
+            prev_x: width as f32,
             // width of the pipe
-            w: 80,
+            w: config.width,
             // How fast
-            speed: 6.0,
+            speed: config.speed,
+            variant,
 
             height,
             width,
+            center_y,
+            spacing,
+            age: 0,
         }
     }
 
-    // Did this pipe hit a bird?
-    pub fn hits(&self, bird: &Bird) -> bool {
-        if (bird.y - bird.r) < self.top as f32
-            || (bird.y + bird.r) > (self.height as f32 - self.bottom as f32)
-        {
-            if bird.x > self.x && bird.x < self.x + self.w as f32 {
-                return true;
-            }
+    // Center and height of the gap when the pipe spawned
+    pub fn spawned(&self) -> (f32, f32) {
+        (self.center_y, self.spacing)
+    }
+
+    // The pairs the bird has to get through, left to right
+    pub fn columns(&self) -> Vec<Column> {
+        let first = Column {
+            x: self.x,
+            prev_x: self.prev_x,
+            top: self.top,
+            bottom: self.bottom,
+        };
+        match self.variant {
+            Variant::Staggered { offset, center } => vec![
+                first,
+                Column {
+                    x: self.x + offset,
+                    prev_x: self.prev_x + offset,
+                    top: center - self.spacing / 2.0,
+                    bottom: self.height as f32 - (center + self.spacing / 2.0),
+                },
+            ],
+            _ => vec![first],
         }
-        false
     }
 
-    pub fn show(&self, canvas: &mut Canvas<Window>) {
-        Renderer::rect(canvas, self.x as i32, 0, self.w as u32, self.top as u32).unwrap();
-        Renderer::rect(
-            canvas,
-            self.x as i32,
-            self.height as i32 - self.bottom as i32,
-            self.w as u32,
-            self.bottom as u32,
-        )
-        .unwrap();
+    // Did the top half of this pipe hit a bird?
+    pub fn hits_top(&self, bird: &Bird) -> bool {
+        self.columns()
+            .iter()
+            .any(|c| circle_hits_rect(bird.x, bird.y, bird.r, c.x, 0.0, self.w as f32, c.top))
+    }
+
+    // Did the bottom half of this pipe hit a bird?
+    pub fn hits_bottom(&self, bird: &Bird) -> bool {
+        self.columns().iter().any(|c| {
+            let y = self.height as f32 - c.bottom;
+            circle_hits_rect(bird.x, bird.y, bird.r, c.x, y, self.w as f32, c.bottom)
+        })
+    }
+
+    // Distance along the ray to the first part of the pipe it hits, see ray_hits_rect
+    pub fn raycast(&self, origin: (f32, f32), dir: (f32, f32)) -> Option<f32> {
+        let (w, height) = (self.w as f32, self.height as f32);
+        self.columns()
+            .iter()
+            .flat_map(|c| {
+                let top = ray_hits_rect(origin, dir, c.x, 0.0, w, c.top);
+                let bottom = ray_hits_rect(origin, dir, c.x, height - c.bottom, w, c.bottom);
+                top.into_iter().chain(bottom)
+            })
+            .fold(None, |closest: Option<f32>, t| {
+                Some(closest.map_or(t, |c| c.min(t)))
+            })
+    }
+
+    pub fn show(&self, canvas: &mut dyn DrawTarget, alpha: f32) {
+        for column in self.columns() {
+            let x = column.prev_x + (column.x - column.prev_x) * alpha;
+            canvas
+                .rect(x as i32, 0, self.w as u32, column.top as u32)
+                .unwrap();
+            canvas
+                .rect(
+                    x as i32,
+                    self.height as i32 - column.bottom as i32,
+                    self.w as u32,
+                    column.bottom as u32,
+                )
+                .unwrap();
+        }
     }
 
     // Update the pipe
     pub fn update(&mut self) {
+        self.prev_x = self.x;
         self.x -= self.speed;
+        self.age += 1;
+        match self.variant {
+            Variant::Oscillating { amplitude, period } => {
+                let center = self.center_y + amplitude * self.cycle(period).sin();
+                self.set_gap(center, self.spacing);
+            }
+            Variant::Closing { min_gap, period } => {
+                // Starts fully open
+                let open = 0.5 + 0.5 * self.cycle(period).cos();
+                self.set_gap(self.center_y, min_gap + (self.spacing - min_gap) * open);
+            }
+            Variant::Static | Variant::Staggered { .. } => {}
+        }
+    }
+
+    // How far along a movement of period frames the pipe is, in radians
+    fn cycle(&self, period: u32) -> f32 {
+        self.age as f32 * 2.0 * PI / period.max(1) as f32
+    }
+
+    fn set_gap(&mut self, center: f32, gap: f32) {
+        self.top = center - gap / 2.0;
+        self.bottom = self.height as f32 - (center + gap / 2.0);
+    }
+
+    // Right edge of the last column
+    fn end(&self) -> f32 {
+        let last = self.columns().last().map_or(self.x, |c| c.x);
+        last + self.w as f32
+    }
+
+    // Did the bird fly past this pipe during the last update?
+    pub fn passed(&self, bird: &Bird) -> bool {
+        let end = self.end();
+        end < bird.x && end + self.speed >= bird.x
     }
 
     // Has it moved offscreen?
     pub fn offscreen(&self) -> bool {
-        if (self.x as i32) < -self.w {
+        if (self.end() as i32) < 0 {
             return true;
         }
         return false;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flappy::testing;
+
+    // Pipe from x = 100 to 180, gap from y = 237.5 to 362.5
+    fn pipe() -> Pipe {
+        let mut pipe = moving(Variant::Static);
+        pipe.x = 100.0;
+        pipe
+    }
+
+    fn hits(pipe: &Pipe, bird: &Bird) -> bool {
+        pipe.hits_top(bird) || pipe.hits_bottom(bird)
+    }
+
+    // Center and height of the empty space right now
+    fn center(pipe: &Pipe) -> f32 {
+        (pipe.top + pipe.height as f32 - pipe.bottom) / 2.0
+    }
+
+    fn gap(pipe: &Pipe) -> f32 {
+        pipe.height as f32 - pipe.top - pipe.bottom
+    }
+
+    fn bird_at(x: f32, y: f32) -> Bird {
+        let mut bird = testing::bird();
+        bird.x = x;
+        bird.y = y;
+        bird
+    }
+
+    #[test]
+    fn flies_through_the_gap() {
+        let pipe = pipe();
+        assert!(!hits(&pipe, &bird_at(140.0, 300.0)));
+        assert!(!hits(&pipe, &bird_at(140.0, 237.5 + 12.5)));
+        assert!(!hits(&pipe, &bird_at(140.0, 362.5 - 12.5)));
+    }
+
+    #[test]
+    fn hits_inside_the_pipe() {
+        let pipe = pipe();
+        assert!(pipe.hits_top(&bird_at(140.0, 100.0)));
+        assert!(pipe.hits_top(&bird_at(140.0, 237.5 + 11.0)));
+        assert!(pipe.hits_bottom(&bird_at(140.0, 500.0)));
+        assert!(pipe.hits_bottom(&bird_at(140.0, 362.5 - 11.0)));
+    }
+
+    #[test]
+    fn hits_leading_and_trailing_edges() {
+        let pipe = pipe();
+        // Center outside the pipe but the radius reaches it
+        assert!(pipe.hits_top(&bird_at(100.0 - 11.0, 100.0)));
+        assert!(pipe.hits_bottom(&bird_at(180.0 + 11.0, 500.0)));
+        assert!(!hits(&pipe, &bird_at(100.0 - 13.0, 100.0)));
+        assert!(!hits(&pipe, &bird_at(180.0 + 13.0, 500.0)));
+    }
+
+    #[test]
+    fn hits_corners_by_distance() {
+        let pipe = pipe();
+        // 8^2 + 8^2 < 12^2, the circle covers the corner
+        assert!(pipe.hits_top(&bird_at(100.0 - 8.0, 237.5 + 8.0)));
+        assert!(pipe.hits_bottom(&bird_at(180.0 + 8.0, 362.5 - 8.0)));
+        // 10^2 + 10^2 > 12^2, bounding boxes overlap but the circle misses
+        assert!(!hits(&pipe, &bird_at(100.0 - 10.0, 237.5 + 10.0)));
+        assert!(!hits(&pipe, &bird_at(180.0 + 10.0, 362.5 - 10.0)));
+    }
+
+    #[test]
+    fn misses_far_away_birds() {
+        let pipe = pipe();
+        assert!(!hits(&pipe, &bird_at(64.0, 100.0)));
+        assert!(!hits(&pipe, &bird_at(400.0, 500.0)));
+    }
+
+    #[test]
+    fn bounds_include_the_radius() {
+        let middle = bird_at(64.0, 300.0);
+        assert!(!middle.hits_ceiling() && !middle.hits_floor());
+        assert!(bird_at(64.0, 11.0).hits_ceiling());
+        assert!(!bird_at(64.0, 13.0).hits_ceiling());
+        assert!(bird_at(64.0, 600.0 - 11.0).hits_floor());
+        assert!(!bird_at(64.0, 600.0 - 13.0).hits_floor());
+    }
+
+    fn moving(variant: Variant) -> Pipe {
+        Pipe::shaped(800, 600, 300.0, &PipeConfig::default(), variant)
+    }
+
+    #[test]
+    fn oscillating_gap_comes_back() {
+        let mut pipe = moving(Variant::Oscillating {
+            amplitude: 50.0,
+            period: 100,
+        });
+        for _ in 0..25 {
+            pipe.update();
+        }
+        assert!((center(&pipe) - 350.0).abs() < 0.01);
+        for _ in 0..75 {
+            pipe.update();
+        }
+        assert!((center(&pipe) - 300.0).abs() < 0.01);
+        assert!((gap(&pipe) - 125.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn closing_gap_opens_again() {
+        let mut pipe = moving(Variant::Closing {
+            min_gap: 60.0,
+            period: 100,
+        });
+        for _ in 0..50 {
+            pipe.update();
+        }
+        assert!((gap(&pipe) - 60.0).abs() < 0.01);
+        assert!((center(&pipe) - 300.0).abs() < 0.01);
+        for _ in 0..50 {
+            pipe.update();
+        }
+        assert!((gap(&pipe) - 125.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn staggered_pipe_has_a_second_pair() {
+        let mut pipe = moving(Variant::Staggered {
+            offset: 200.0,
+            center: 150.0,
+        });
+        pipe.x = 100.0;
+        // Through the first gap, into the top of the second pair
+        assert!(!hits(&pipe, &bird_at(140.0, 300.0)));
+        assert!(pipe.hits_top(&bird_at(340.0, 60.0)));
+        assert!(pipe.hits_bottom(&bird_at(340.0, 300.0)));
+        assert!(!hits(&pipe, &bird_at(340.0, 150.0)));
+        assert_eq!(pipe.columns().len(), 2);
+    }
+}
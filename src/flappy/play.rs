@@ -0,0 +1,210 @@
+use crate::flappy::bird::Bird;
+use crate::flappy::episode::Flight;
+use crate::flappy::generator::PipeGenerator;
+use crate::flappy::physics::PhysicsConfig;
+use crate::flappy::pipe::PipeConfig;
+use crate::flappy::recording::Recording;
+use crate::flappy::theme::Theme;
+use crate::nn::nn::NeuralNetwork;
+use crate::renderer::target::DrawTarget;
+
+/**
+ * Human playable mode: the player flaps with the spacebar
+ * while optional AI ghost birds fly the same pipes
+ */
+pub struct Play {
+    // The player's run, flapped by hand
+    player: Flight,
+    flap_pending: bool,
+    // Every ghost on its own copy of the same course
+    ghosts: Vec<Flight>,
+    high_score: u32,
+    width: u32,
+    height: u32,
+    // Pipe course of every run
+    generator: PipeGenerator,
+    physics: PhysicsConfig,
+    // Where to save the recording of every run
+    record_dir: Option<String>,
+    runs: u32,
+}
+
+impl Play {
+    pub fn new(width: u32, height: u32, total_ghosts: usize) -> Play {
+        let seed = rand::random();
+        let generator = PipeGenerator::new(width, height, seed, PipeConfig::default());
+        let fly = || {
+            let bird = Bird::new(width, height, NeuralNetwork::new(5, 8, 2));
+            Flight::start(bird, &generator, seed)
+        };
+        Play {
+            player: fly(),
+            flap_pending: false,
+            ghosts: (0..total_ghosts).map(|_| fly()).collect(),
+            high_score: 0,
+            width,
+            height,
+            generator,
+            physics: PhysicsConfig::default(),
+            record_dir: None,
+            runs: 0,
+        }
+    }
+
+    // Save every run as a recording in dir
+    pub fn record_to(&mut self, dir: &str) -> Result<(), String> {
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        self.record_dir = Some(dir.to_string());
+        Ok(())
+    }
+
+    // Every ghost flies bird's brain, senses and action head instead of a random brain
+    pub fn set_ghosts(&mut self, bird: &Bird) {
+        for ghost in self.ghosts.iter_mut() {
+            ghost.bird = bird.reborn();
+        }
+        self.restart();
+    }
+
+    // Gap, speed, width and interval of the pipes
+    pub fn set_pipe_config(&mut self, config: PipeConfig) {
+        self.generator.base = config;
+        self.restart();
+    }
+
+    // Gravity, flaps and size of the player and the ghosts
+    pub fn set_physics(&mut self, physics: PhysicsConfig) {
+        self.physics = physics;
+        self.restart();
+    }
+
+    fn alive(&self) -> bool {
+        self.player.outcome.is_none()
+    }
+
+    // Spacebar: flap on the next frame while alive, start over once dead
+    pub fn flap(&mut self) {
+        if self.alive() {
+            self.flap_pending = true;
+        } else {
+            self.restart();
+        }
+    }
+
+    // A copy of bird at the start of the course of seed, with the current physics and pipes
+    fn launch(&self, bird: &Bird, seed: u64) -> Flight {
+        let mut bird = bird.reborn();
+        bird.set_physics(self.physics);
+        bird.set_pipe_config(self.generator.base);
+        Flight::start(bird, &self.generator, seed)
+    }
+
+    // Start over with the same ghost brains
+    pub fn restart(&mut self) {
+        let seed = rand::random();
+        self.player = self.launch(&self.player.bird, seed);
+        self.ghosts = self
+            .ghosts
+            .iter()
+            .map(|ghost| self.launch(&ghost.bird, seed))
+            .collect();
+        self.flap_pending = false;
+    }
+
+    pub fn update(&mut self) {
+        if !self.alive() {
+            return;
+        }
+        let flap = std::mem::take(&mut self.flap_pending);
+        self.player.step(|bird, _| {
+            if flap {
+                bird.up();
+            }
+            None
+        });
+        self.high_score = self.high_score.max(self.player.pipes_passed);
+        if !self.alive() {
+            self.save_recording();
+        }
+
+        for ghost in self.ghosts.iter_mut().filter(|g| g.outcome.is_none()) {
+            ghost.step(|bird, pipes| bird.think(pipes));
+        }
+    }
+
+    fn save_recording(&mut self) {
+        if let Some(dir) = &self.record_dir {
+            self.runs += 1;
+            let path = format!("{}/run-{}.rec", dir, self.runs);
+            match Recording::of(&self.player).save(&path) {
+                Ok(()) => println!("Saved {}", path),
+                Err(e) => println!("{}", e),
+            }
+        }
+    }
+
+    pub fn draw(
+        &mut self,
+        canvas: &mut dyn DrawTarget,
+        theme: &Theme,
+        alpha: f32,
+    ) -> Result<(), String> {
+        theme.background(canvas, self.player.scrolled(alpha))?;
+        for pipe in self.player.pipes.iter() {
+            theme.pipe(canvas, pipe, alpha)?;
+        }
+
+        let flying: Vec<&Flight> = self.ghosts.iter().filter(|g| g.outcome.is_none()).collect();
+        theme.birds(canvas, flying.iter().map(|g| &g.bird), theme.ghost, alpha)?;
+        theme.bird(canvas, &self.player.bird, theme.player, alpha)?;
+
+        canvas.set_color(theme.text);
+        let score = self.player.pipes_passed;
+        canvas.text(10, 10, 4, &format!("SCORE {}", score))?;
+        canvas.text(10, 40, 2, &format!("BEST {}", self.high_score))?;
+        if !self.ghosts.is_empty() {
+            canvas.text(
+                10,
+                56,
+                2,
+                &format!("GHOSTS {}/{}", flying.len(), self.ghosts.len()),
+            )?;
+        }
+        if !self.alive() {
+            // Glyphs are 4 font pixels wide including spacing
+            let center = |text: &str, scale: u32| {
+                self.width as i32 / 2 - (text.len() as u32 * 4 * scale) as i32 / 2
+            };
+            let y = self.height as i32 / 2 - 20;
+            // On a panel so pipes don't run through the text
+            canvas.set_color(theme.background);
+            canvas.rounded_rect(center("GAME OVER", 6) - 20, y - 20, 256, 90, 12)?;
+            canvas.set_color(theme.text);
+            canvas.text(center("GAME OVER", 6), y, 6, "GAME OVER")?;
+            let restart = "PRESS SPACE TO RESTART";
+            canvas.text(center(restart, 2), y + 40, 2, restart)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flappy::testing;
+
+    #[test]
+    fn ghosts_fly_a_trained_brain() {
+        let mut play = Play::new(800, 600, 3);
+        let trained = testing::bird();
+        assert!(trained.check_brain().is_ok());
+        play.set_ghosts(&trained);
+        for ghost in play.ghosts.iter() {
+            assert_eq!(ghost.bird.brain.genome(), trained.brain.genome());
+            assert_ne!(ghost.bird.id, trained.id);
+        }
+
+        let wrong_inputs = Bird::new(800, 600, NeuralNetwork::new(4, 8, 2));
+        assert!(wrong_inputs.check_brain().is_err());
+    }
+}
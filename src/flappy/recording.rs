@@ -0,0 +1,303 @@
+use crate::flappy::bird::Bird;
+use crate::flappy::episode::Flight;
+use crate::flappy::physics::{Impulse, PhysicsConfig};
+use crate::flappy::pipe::{Pipe, PipeConfig, Variant};
+use crate::flappy::sensors::SensorSet;
+use crate::flappy::theme::Theme;
+use crate::nn::nn::NeuralNetwork;
+use crate::renderer::target::DrawTarget;
+use std::fs;
+
+const HEADER: &str = "rusty-birds recording v1";
+
+/**
+ * Everything needed to play a bird's run again:
+ * the pipe course and the flap decision of every frame
+ */
+#[derive(Debug, Clone)]
+pub struct Recording {
+    // Seed the pipe course was generated from
+    pub seed: u64,
+    pub width: u32,
+    pub height: u32,
+    // How the bird fell and flapped
+    pub physics: PhysicsConfig,
+    // Every spawned pipe with the frame it came at
+    pub pipes: Vec<(u32, Pipe)>,
+    pub flaps: Vec<bool>,
+    // How hard every flap was, empty when they were all full flaps
+    pub strengths: Vec<f32>,
+}
+
+impl Recording {
+    pub fn new(seed: u64, width: u32, height: u32) -> Recording {
+        Recording {
+            seed,
+            width,
+            height,
+            physics: PhysicsConfig::default(),
+            pipes: Vec::new(),
+            flaps: Vec::new(),
+            strengths: Vec::new(),
+        }
+    }
+
+    // The run a flight has flown so far
+    pub fn of(flight: &Flight) -> Recording {
+        let bird = &flight.bird;
+        Recording {
+            seed: flight.seed,
+            width: bird.width(),
+            height: bird.height(),
+            physics: bird.physics,
+            pipes: flight.spawned.clone(),
+            flaps: bird.flaps.clone(),
+            strengths: bird.strengths.clone(),
+        }
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let mut out = format!("{}\nseed {}\nsize {} {}\n", HEADER, self.seed, self.width, self.height);
+        let p = &self.physics;
+        out.push_str(&format!(
+            "physics {} {} {} {} {} {} {}\n",
+            p.gravity,
+            p.lift,
+            p.impulse.name(),
+            p.terminal_velocity,
+            p.drag,
+            p.cooldown,
+            p.radius
+        ));
+        for (frame, pipe) in self.pipes.iter() {
+            let (center, gap) = pipe.spawned();
+            let variant = match pipe.variant {
+                Variant::Static => String::new(),
+                Variant::Oscillating { amplitude, period } => {
+                    format!(" oscillating {} {}", amplitude, period)
+                }
+                Variant::Closing { min_gap, period } => format!(" closing {} {}", min_gap, period),
+                Variant::Staggered { offset, center } => {
+                    format!(" staggered {} {}", offset, center)
+                }
+            };
+            out.push_str(&format!(
+                "pipe {} {} {} {} {}{}\n",
+                frame, center, gap, pipe.speed, pipe.w, variant
+            ));
+        }
+        let flaps: String = self.flaps.iter().map(|&f| if f { '1' } else { '0' }).collect();
+        out.push_str(&format!("flaps {}\n", flaps));
+        if self.strengths.iter().any(|&s| s != 1.0) {
+            let strengths: Vec<String> = self.strengths.iter().map(|s| s.to_string()).collect();
+            out.push_str(&format!("strengths {}\n", strengths.join(" ")));
+        }
+        fs::write(path, out).map_err(|e| format!("Unable to write {}: {}", path, e))
+    }
+
+    pub fn load(path: &str) -> Result<Recording, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Unable to read {}: {}", path, e))?;
+        let mut lines = text.lines();
+        if lines.next() != Some(HEADER) {
+            return Err(format!("{} is not a recording", path));
+        }
+        let bad = |line: &str| format!("Malformed line in {}: {}", path, line);
+
+        let mut recording = Recording::new(0, 0, 0);
+        for line in lines {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                ["seed", seed] => recording.seed = seed.parse().map_err(|_| bad(line))?,
+                ["size", w, h] => {
+                    recording.width = w.parse().map_err(|_| bad(line))?;
+                    recording.height = h.parse().map_err(|_| bad(line))?;
+                }
+                // Older recordings were all made with the original physics
+                ["physics", gravity, lift, impulse, terminal, drag, cooldown, radius] => {
+                    recording.physics = PhysicsConfig {
+                        gravity: gravity.parse().map_err(|_| bad(line))?,
+                        lift: lift.parse().map_err(|_| bad(line))?,
+                        impulse: Impulse::parse(impulse).map_err(|_| bad(line))?,
+                        terminal_velocity: terminal.parse().map_err(|_| bad(line))?,
+                        drag: drag.parse().map_err(|_| bad(line))?,
+                        cooldown: cooldown.parse().map_err(|_| bad(line))?,
+                        radius: radius.parse().map_err(|_| bad(line))?,
+                    }
+                }
+                ["pipe", frame, center, shape @ ..] => {
+                    // Older recordings only have classic pipes
+                    let mut config = PipeConfig::default();
+                    let mut variant = Variant::Static;
+                    if let [gap, speed, w, rest @ ..] = shape {
+                        config.gap = gap.parse().map_err(|_| bad(line))?;
+                        config.speed = speed.parse().map_err(|_| bad(line))?;
+                        config.width = w.parse().map_err(|_| bad(line))?;
+                        variant = match rest {
+                            [] => Variant::Static,
+                            ["oscillating", amplitude, period] => Variant::Oscillating {
+                                amplitude: amplitude.parse().map_err(|_| bad(line))?,
+                                period: period.parse().map_err(|_| bad(line))?,
+                            },
+                            ["closing", min_gap, period] => Variant::Closing {
+                                min_gap: min_gap.parse().map_err(|_| bad(line))?,
+                                period: period.parse().map_err(|_| bad(line))?,
+                            },
+                            ["staggered", offset, center] => Variant::Staggered {
+                                offset: offset.parse().map_err(|_| bad(line))?,
+                                center: center.parse().map_err(|_| bad(line))?,
+                            },
+                            _ => return Err(bad(line)),
+                        };
+                    } else if !shape.is_empty() {
+                        return Err(bad(line));
+                    }
+                    let center = center.parse().map_err(|_| bad(line))?;
+                    let (width, height) = (recording.width, recording.height);
+                    let pipe = Pipe::shaped(width, height, center, &config, variant);
+                    recording
+                        .pipes
+                        .push((frame.parse().map_err(|_| bad(line))?, pipe));
+                }
+                ["flaps"] => recording.flaps = Vec::new(),
+                ["flaps", flaps] => recording.flaps = flaps.chars().map(|c| c == '1').collect(),
+                ["strengths", strengths @ ..] => {
+                    recording.strengths = strengths
+                        .iter()
+                        .map(|s| s.parse().map_err(|_| bad(line)))
+                        .collect::<Result<Vec<f32>, String>>()?
+                }
+                [] => {}
+                _ => return Err(bad(line)),
+            }
+        }
+        Ok(recording)
+    }
+
+    /**
+     * Replays the run and pairs the network inputs of every
+     * frame with the recorded flap, for supervised learning
+     */
+    pub fn training_pairs(&self) -> Vec<(Vec<f32>, bool)> {
+        let mut replay = Replay::new(self.clone());
+        while replay.step() {}
+        replay.pairs
+    }
+
+    pub fn export_training_pairs(&self, path: &str) -> Result<usize, String> {
+        let pairs = self.training_pairs();
+        let names = SensorSet::default().names();
+        let mut out = format!("{},flap\n", names.join(","));
+        for (inputs, flap) in pairs.iter() {
+            let row: Vec<String> = inputs.iter().map(|x| x.to_string()).collect();
+            out.push_str(&format!("{},{}\n", row.join(","), *flap as u8));
+        }
+        fs::write(path, out).map_err(|e| format!("Unable to write {}: {}", path, e))?;
+        Ok(pairs.len())
+    }
+}
+
+/**
+ * Plays a recording back frame by frame, flying the recorded
+ * flaps through the recorded pipes so the run is reproduced exactly
+ */
+pub struct Replay {
+    recording: Recording,
+    flight: Flight,
+    pairs: Vec<(Vec<f32>, bool)>,
+}
+
+impl Replay {
+    pub fn new(recording: Recording) -> Replay {
+        let mut bird = Bird::new(recording.width, recording.height, NeuralNetwork::new(5, 8, 2));
+        bird.set_physics(recording.physics);
+        let flight = Flight::replay(bird, recording.pipes.clone(), recording.seed);
+        Replay {
+            recording,
+            flight,
+            pairs: Vec::new(),
+        }
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.flight.frame
+    }
+
+    pub fn pipes_passed(&self) -> u32 {
+        self.flight.pipes_passed
+    }
+
+    pub fn finished(&self) -> bool {
+        self.flight.outcome.is_some() || self.frame() as usize >= self.recording.flaps.len()
+    }
+
+    // Advance one frame, false once the run is over
+    pub fn step(&mut self) -> bool {
+        if self.finished() {
+            return false;
+        }
+        let flap = self.recording.flaps[self.frame() as usize];
+        let strengths = &self.recording.strengths;
+        let frame = self.flight.step(|bird, pipes| {
+            let inputs = bird.inputs(pipes);
+            if flap {
+                let strength = strengths.get(bird.strengths.len()).cloned();
+                bird.flap(strength.unwrap_or(1.0));
+            }
+            inputs.map(|inputs| (inputs, flap))
+        });
+        if let Some(pair) = frame.decision {
+            self.pairs.push(pair);
+        }
+        !self.finished()
+    }
+
+    pub fn draw(
+        &mut self,
+        canvas: &mut dyn DrawTarget,
+        theme: &Theme,
+        alpha: f32,
+    ) -> Result<(), String> {
+        theme.background(canvas, self.flight.scrolled(alpha))?;
+        for pipe in self.flight.pipes.iter() {
+            theme.pipe(canvas, pipe, alpha)?;
+        }
+        theme.bird(canvas, &self.flight.bird, theme.player, alpha)?;
+
+        canvas.set_color(theme.text);
+        let progress = format!("REPLAY {}/{}", self.frame(), self.recording.flaps.len());
+        canvas.text(10, 10, 2, &progress)?;
+        canvas.text(10, 26, 2, &format!("SCORE {}", self.pipes_passed()))?;
+        if self.finished() {
+            let crashed = self.flight.outcome.is_some();
+            canvas.text(10, 42, 2, if crashed { "CRASHED" } else { "END" })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flappy::testing;
+
+    #[test]
+    fn replays_a_flight_exactly() {
+        let bird = testing::bird();
+        let course = testing::course();
+        let mut flight = Flight::new(&bird, &course, 42);
+        // Hover around the middle until a pipe gets in the way
+        while flight.outcome.is_none() && flight.frame < 2000 {
+            flight.step(|bird, _| {
+                if bird.y > 320.0 && bird.velocity > 0.0 {
+                    bird.up();
+                }
+                None
+            });
+        }
+        let mut replay = Replay::new(Recording::of(&flight));
+        while replay.step() {}
+        assert_eq!(replay.frame(), flight.frame);
+        assert_eq!(replay.pipes_passed(), flight.pipes_passed);
+        assert_eq!(replay.flight.outcome, flight.outcome);
+    }
+}
@@ -0,0 +1,208 @@
+use crate::flappy::action::ActionHead;
+use crate::flappy::bird::Bird;
+use crate::flappy::env::{FlappyEnv, ObservationSpace, Reward, VecEnv};
+use crate::flappy::episode::{Episode, MAX_FRAMES};
+use crate::flappy::generator::PipeGenerator;
+use crate::flappy::stats::GenerationStats;
+use crate::nn::nn::Gradients;
+use std::time::Instant;
+
+// Step size of gradient ascent, higher than for supervised learning
+// since gradients are averaged over every decision of a batch
+pub const LEARNING_RATE: f32 = 2.0;
+// Chances are kept this far from 0 and 1 so log probabilities stay finite
+const MIN_CHANCE: f32 = 1e-4;
+
+// One decision of an episode and what came of it until the next
+#[derive(Debug, Clone, PartialEq)]
+struct Step {
+    inputs: Vec<f32>,
+    flapped: bool,
+    reward: f32,
+}
+
+/**
+ * Trains one brain with REINFORCE: fly a batch of headless episodes
+ * with a stochastic policy, then make every flap or glide more likely
+ * the better it turned out compared to the batch, the baseline
+ */
+#[derive(Debug, Clone)]
+pub struct Reinforce {
+    // Brain, sensors and physics of the policy being trained
+    pub bird: Bird,
+    course: PipeGenerator,
+    pub reward: Reward,
+    pub space: ObservationSpace,
+    // Weight of a reward one decision later
+    pub discount: f32,
+    // Episodes per update
+    pub batch: u32,
+    pub max_frames: u32,
+    // Updates done and frames flown in all of them
+    pub updates: u32,
+    pub frames: u64,
+    next_seed: u64,
+}
+
+impl Reinforce {
+    pub fn new(mut bird: Bird, course: PipeGenerator, seed: u64) -> Result<Reinforce, String> {
+        bird.head = ActionHead::Stochastic;
+        bird.brain.set_learning_rate(LEARNING_RATE);
+        if bird.brain.output_nodes != bird.head.outputs() {
+            return Err(format!(
+                "A stochastic policy needs a brain with 1 output, not {}",
+                bird.brain.output_nodes
+            ));
+        }
+        Ok(Reinforce {
+            bird,
+            course,
+            reward: Reward::Shaped,
+            space: ObservationSpace::Inputs,
+            discount: 0.95,
+            batch: 10,
+            max_frames: MAX_FRAMES,
+            updates: 0,
+            frames: 0,
+            next_seed: seed,
+        })
+    }
+
+    /**
+     * Every decision of a batch of episodes flown side by side,
+     * on courses seeded counting up from seed, and the episodes
+     */
+    fn rollouts(&self, seed: u64) -> Result<Vec<(Vec<Step>, Episode)>, String> {
+        let envs = (0..self.batch)
+            .map(|_| {
+                let mut env = FlappyEnv::new(&self.bird, &self.course);
+                env.reward = self.reward;
+                env.space = self.space;
+                env.max_frames = self.max_frames;
+                env
+            })
+            .collect();
+        let mut envs = VecEnv::new(envs, seed);
+        envs.auto_reset = false;
+        let mut observations = envs.reset();
+        let mut steps = vec![Vec::new(); self.batch as usize];
+        let mut dones: Vec<bool> = envs.envs().iter().map(|env| env.done()).collect();
+        while dones.iter().any(|&done| !done) {
+            let actions = envs.act(&observations);
+            let batch = envs.step(&actions)?;
+            // Environments done before this step only glided along
+            for (i, steps) in steps.iter_mut().enumerate().filter(|&(i, _)| !dones[i]) {
+                steps.push(Step {
+                    inputs: observations[i].clone(),
+                    flapped: actions[i].is_some(),
+                    reward: batch.rewards[i],
+                });
+            }
+            observations = batch.observations;
+            dones = batch.dones;
+        }
+        let episodes = envs.envs().iter().map(|env| env.episode());
+        Ok(steps.into_iter().zip(episodes).collect())
+    }
+
+    // Discounted sum of every reward from each step on
+    fn returns(&self, steps: &[Step]) -> Vec<f32> {
+        let mut returns = vec![0.0; steps.len()];
+        let mut later = 0.0;
+        for (i, step) in steps.iter().enumerate().rev() {
+            later = step.reward + self.discount * later;
+            returns[i] = later;
+        }
+        returns
+    }
+
+    // Flies a batch of episodes and takes one step up the policy gradient
+    pub fn update(&mut self) -> Result<GenerationStats, String> {
+        let started = Instant::now();
+        let first_seed = self.next_seed;
+        self.next_seed += self.batch as u64;
+
+        let mut steps = Vec::new();
+        let mut returns = Vec::new();
+        let mut scores = Vec::new();
+        let mut pipes = 0;
+        for (episode_steps, episode) in self.rollouts(first_seed)? {
+            returns.extend(self.returns(&episode_steps));
+            steps.extend(episode_steps);
+            scores.push(episode.frames as u64);
+            pipes = pipes.max(episode.pipes_passed);
+        }
+        self.frames += scores.iter().sum::<u64>();
+
+        // How much better every decision turned out than the average
+        let n = returns.len().max(1) as f32;
+        let mean = returns.iter().sum::<f32>() / n;
+        let stddev = (returns.iter().map(|g| (g - mean).powi(2)).sum::<f32>() / n).sqrt();
+        let brain = &self.bird.brain;
+        let mut gradients = Gradients::zero(brain);
+        for (step, g) in steps.iter().zip(returns.iter()) {
+            let advantage = (g - mean) / stddev.max(f32::EPSILON);
+            let chance = brain.predict(&step.inputs)?[0].clamp(MIN_CHANCE, 1.0 - MIN_CHANCE);
+            // Change of the log probability of what was done, the loss is its negative
+            let log_slope = if step.flapped {
+                1.0 / chance
+            } else {
+                -1.0 / (1.0 - chance)
+            };
+            gradients.add(&brain.gradients(&step.inputs, &[-advantage * log_slope])?)?;
+        }
+        gradients.scale(1.0 / n);
+        self.bird.brain.descend(&gradients)?;
+        self.updates += 1;
+
+        Ok(GenerationStats {
+            generation: self.updates,
+            pipes,
+            seconds: started.elapsed().as_secs_f32(),
+            seed: first_seed,
+            population: self.batch as usize,
+            hidden_nodes: self.bird.brain.hidden_nodes,
+            frames: self.frames,
+            ..GenerationStats::default()
+        }
+        .with_scores(&scores))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flappy::testing;
+
+    #[test]
+    fn discounts_later_rewards() {
+        let bird = testing::bird_with(1);
+        let course = testing::course();
+        let mut trainer = Reinforce::new(bird, course, 0).unwrap();
+        trainer.discount = 0.5;
+        let step = |reward| Step {
+            inputs: Vec::new(),
+            flapped: false,
+            reward,
+        };
+        let returns = trainer.returns(&[step(1.0), step(0.0), step(4.0)]);
+        assert_eq!(returns, vec![2.0, 2.0, 4.0]);
+    }
+
+    #[test]
+    fn updates_count_every_frame() {
+        let bird = testing::bird_with(1);
+        let course = testing::course();
+        let mut trainer = Reinforce::new(bird, course, 0).unwrap();
+        trainer.batch = 3;
+        let before = trainer.bird.brain.genome();
+        let stats = trainer.update().unwrap();
+        assert_eq!(stats.frames, trainer.frames);
+        assert!(stats.frames >= 3);
+        assert_ne!(trainer.bird.brain.genome(), before);
+
+        let two_outputs = testing::bird();
+        let course = testing::course();
+        assert!(Reinforce::new(two_outputs, course, 0).is_err());
+    }
+}
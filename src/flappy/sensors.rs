@@ -0,0 +1,260 @@
+use crate::flappy::bird::Bird;
+use crate::flappy::physics::PhysicsConfig;
+use crate::flappy::pipe::{Column, Pipe, PipeConfig};
+use crate::flappy::vision::{self, DEFAULT_RAYS, MAX_RAYS};
+
+/**
+ * How far readings can go, from the screen, the physics of the
+ * bird and the pipes it flies through
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    pub width: f32,
+    pub height: f32,
+    // Fastest the bird moves up or down
+    pub velocity: f32,
+    // Slowest the pipes come at, harder levels only speed them up
+    pub pipe_speed: f32,
+}
+
+impl Limits {
+    pub fn new(width: u32, height: u32, physics: &PhysicsConfig, pipes: &PipeConfig) -> Limits {
+        Limits {
+            width: width as f32,
+            height: height as f32,
+            velocity: physics.top_speed(height as f32),
+            pipe_speed: pipes.speed,
+        }
+    }
+}
+
+// Something a bird can feel, each gives one or more network inputs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sensor {
+    // x, top and bottom of the closest pipe ahead
+    Pipe,
+    // Height and velocity of the bird
+    Bird,
+    // x, top and bottom of the pipe after that
+    SecondPipe,
+    // How far above or below the center of the next gap the bird is
+    GapOffset,
+    // Frames until the next pipe reaches the bird
+    TimeToImpact,
+    // Distance to whatever is first hit in this many directions
+    Rays(u32),
+    // Whether the bird flapped on the last frame
+    PreviousAction,
+}
+
+impl Sensor {
+    pub fn parse(name: &str) -> Result<Sensor, String> {
+        match name {
+            "pipe" => Ok(Sensor::Pipe),
+            "bird" => Ok(Sensor::Bird),
+            "second-pipe" => Ok(Sensor::SecondPipe),
+            "gap" => Ok(Sensor::GapOffset),
+            "impact" => Ok(Sensor::TimeToImpact),
+            "rays" => Ok(Sensor::Rays(DEFAULT_RAYS)),
+            "flap" => Ok(Sensor::PreviousAction),
+            _ => match name.strip_prefix("rays:").map(|n| n.parse()) {
+                Some(Ok(rays)) if rays > 0 && rays <= MAX_RAYS => Ok(Sensor::Rays(rays)),
+                Some(_) => Err(format!("{} needs 1 to {} rays", name, MAX_RAYS)),
+                None => Err(format!(
+                    "Unknown sensor {}, use pipe, bird, second-pipe, gap, impact, rays[:n] or flap",
+                    name
+                )),
+            },
+        }
+    }
+
+    // Name of every input this sensor gives
+    pub fn names(self) -> Vec<String> {
+        let names: &[&str] = match self {
+            Sensor::Pipe => &["pipe_x", "pipe_top", "pipe_bottom"],
+            Sensor::Bird => &["bird_y", "velocity"],
+            Sensor::SecondPipe => &["second_pipe_x", "second_pipe_top", "second_pipe_bottom"],
+            Sensor::GapOffset => &["gap_offset"],
+            Sensor::TimeToImpact => &["time_to_impact"],
+            Sensor::Rays(rays) => {
+                return vision::angles(rays)
+                    .iter()
+                    .map(|angle| format!("ray_{}", angle))
+                    .collect()
+            }
+            Sensor::PreviousAction => &["flapped"],
+        };
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    // Range every input is expected to stay in, in the same order as names
+    pub fn ranges(self, limits: &Limits) -> Vec<(f32, f32)> {
+        let Limits { width, height, .. } = *limits;
+        match self {
+            Sensor::Pipe | Sensor::SecondPipe => vec![(0.0, width), (0.0, height), (0.0, height)],
+            Sensor::Bird => vec![(0.0, height), (-limits.velocity, limits.velocity)],
+            Sensor::GapOffset => vec![(-height, height)],
+            // Read with the same floor on the speed
+            Sensor::TimeToImpact => vec![(0.0, width / limits.pipe_speed.max(0.1))],
+            Sensor::Rays(rays) => vec![(0.0, width); rays as usize],
+            Sensor::PreviousAction => vec![(0.0, 1.0)],
+        }
+    }
+}
+
+/**
+ * The sensors a bird reads before every decision, in order.
+ * The size of the brain's input layer follows from them
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct SensorSet {
+    sensors: Vec<Sensor>,
+}
+
+impl Default for SensorSet {
+    // The five inputs birds always had
+    fn default() -> SensorSet {
+        SensorSet {
+            sensors: vec![Sensor::Pipe, Sensor::Bird],
+        }
+    }
+}
+
+impl SensorSet {
+    pub fn new(sensors: Vec<Sensor>) -> SensorSet {
+        SensorSet { sensors }
+    }
+
+    // A comma separated list like pipe,bird,second-pipe
+    pub fn parse(list: &str) -> Result<SensorSet, String> {
+        let sensors = list
+            .split(',')
+            .map(Sensor::parse)
+            .collect::<Result<Vec<Sensor>, String>>()?;
+        Ok(SensorSet::new(sensors))
+    }
+
+    // How many rays the birds look with, if any
+    pub fn rays(&self) -> Option<u32> {
+        self.sensors.iter().find_map(|sensor| match sensor {
+            Sensor::Rays(rays) => Some(*rays),
+            _ => None,
+        })
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.sensors.iter().flat_map(|s| s.names()).collect()
+    }
+
+    // Inputs the brain needs
+    pub fn size(&self) -> usize {
+        self.names().len()
+    }
+
+    pub fn ranges(&self, limits: &Limits) -> Vec<(f32, f32)> {
+        self.sensors.iter().flat_map(|s| s.ranges(limits)).collect()
+    }
+
+    /**
+     * Raw readings in pixels and frames, see Normalizer for
+     * turning them into network inputs. Pipe edges are y coordinates.
+     * None while a sensor needs a pipe ahead and there's none yet,
+     * rays see the ceiling and floor even then
+     */
+    pub fn read(&self, bird: &Bird, pipes: &[Pipe]) -> Option<Vec<f32>> {
+        let (width, height) = (bird.width() as f32, bird.height() as f32);
+        // Staggered pipes have two columns to get through
+        let mut ahead: Vec<(Column, f32)> = pipes
+            .iter()
+            .flat_map(|p| p.columns().into_iter().map(move |c| (c, p.speed)))
+            .filter(|(c, _)| c.x - bird.x > 0.0)
+            .collect();
+        ahead.sort_by(|a, b| a.0.x.partial_cmp(&b.0.x).unwrap());
+        let next = ahead.first().cloned();
+
+        let mut inputs = Vec::with_capacity(self.size());
+        for sensor in self.sensors.iter() {
+            match sensor {
+                Sensor::Pipe => {
+                    let (next, _) = next?;
+                    inputs.extend_from_slice(&[next.x - bird.x, next.top, height - next.bottom]);
+                }
+                Sensor::Bird => inputs.extend_from_slice(&[bird.y, bird.velocity]),
+                Sensor::SecondPipe => match ahead.get(1) {
+                    Some((second, _)) => inputs.extend_from_slice(&[
+                        second.x - bird.x,
+                        second.top,
+                        height - second.bottom,
+                    ]),
+                    // Nothing yet, as if it were far away and wide open
+                    None => inputs.extend_from_slice(&[width, 0.0, height]),
+                },
+                Sensor::GapOffset => {
+                    let (next, _) = next?;
+                    let center = (next.top + height - next.bottom) / 2.0;
+                    inputs.push(center - bird.y);
+                }
+                Sensor::TimeToImpact => {
+                    let (next, speed) = next?;
+                    inputs.push((next.x - bird.x - bird.r).max(0.0) / speed.max(0.1));
+                }
+                Sensor::Rays(rays) => {
+                    inputs.extend(
+                        vision::look(bird, pipes, *rays)
+                            .iter()
+                            .map(|ray| ray.distance),
+                    );
+                }
+                Sensor::PreviousAction => {
+                    let flapped = bird.flaps.last().cloned().unwrap_or(false);
+                    inputs.push(if flapped { 1.0 } else { 0.0 });
+                }
+            }
+        }
+        Some(inputs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flappy::pipe::Variant;
+    use crate::flappy::testing;
+
+    #[test]
+    fn input_size_follows_the_sensors() {
+        assert_eq!(SensorSet::default().size(), 5);
+        let sensors = SensorSet::parse("pipe,bird,second-pipe,gap,impact,rays,flap").unwrap();
+        assert_eq!(sensors.size(), 5 + 3 + 1 + 1 + 5 + 1);
+        assert_eq!(SensorSet::parse("rays:9").unwrap().size(), 9);
+
+        let bird = testing::bird();
+        let mut pipe = Pipe::shaped(800, 600, 300.0, &PipeConfig::default(), Variant::Static);
+        pipe.x = 400.0;
+        assert_eq!(sensors.read(&bird, &[]), None);
+        let inputs = sensors.read(&bird, &[pipe]).unwrap();
+        assert_eq!(inputs.len(), sensors.size());
+        assert_eq!(sensors.ranges(&bird.limits()).len(), sensors.size());
+        // The bottom edge of the gap as a y coordinate
+        assert_eq!(inputs[2], 362.5);
+        // Faster birds and slower pipes read further
+        let mut slow = bird.clone();
+        slow.set_physics(PhysicsConfig::heavy());
+        slow.set_pipe_config(PipeConfig {
+            speed: 3.0,
+            ..PipeConfig::default()
+        });
+        let impact = SensorSet::parse("bird,impact").unwrap();
+        assert_eq!(
+            impact.ranges(&bird.limits())[1..],
+            [(-24.0, 24.0), (0.0, 800.0 / 6.0)]
+        );
+        assert_eq!(
+            impact.ranges(&slow.limits())[1..],
+            [(-16.0, 16.0), (0.0, 800.0 / 3.0)]
+        );
+        // Rays don't need a pipe to see something
+        let rays = SensorSet::parse("rays:3").unwrap();
+        assert_eq!(rays.read(&bird, &[]).unwrap().len(), 3);
+    }
+}
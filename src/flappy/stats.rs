@@ -0,0 +1,167 @@
+use std::fs::File;
+use std::io::Write;
+
+// Summary of one finished generation
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GenerationStats {
+    pub generation: u32,
+    // Scores are frames survived
+    pub best: u64,
+    pub mean: f32,
+    pub median: f32,
+    pub stddev: f32,
+    // Pipes the longest lasting bird got through
+    pub pipes: u32,
+    // Curriculum difficulty the generation ended at
+    pub level: u32,
+    // Families of first generation birds still around
+    pub diversity: usize,
+    // Mean weight distance between two birds
+    pub genotype_diversity: f32,
+    // Chance two birds act differently in the same spot
+    pub behaviour_diversity: f32,
+    // Generations in a row without a new best score
+    pub stagnant: u32,
+    // Wall clock seconds the generation took
+    pub seconds: f32,
+    // Pipe course seed
+    pub seed: u64,
+    pub population: usize,
+    pub hidden_nodes: i32,
+    // For breeding the next generation
+    pub mutation_rate: f32,
+    pub mutation_size: f32,
+    // Frames flown by all birds so far, to compare how fast methods learn
+    pub frames: u64,
+}
+
+impl GenerationStats {
+    // Fills in best, mean, median and stddev from the scores
+    pub fn with_scores(mut self, scores: &[u64]) -> GenerationStats {
+        if scores.is_empty() {
+            return self;
+        }
+        let mut sorted = scores.to_vec();
+        sorted.sort_unstable();
+        let n = sorted.len();
+        self.best = sorted[n - 1];
+        self.median = if n % 2 == 1 {
+            sorted[n / 2] as f32
+        } else {
+            (sorted[n / 2 - 1] + sorted[n / 2]) as f32 / 2.0
+        };
+        self.mean = sorted.iter().sum::<u64>() as f32 / n as f32;
+        let variance = sorted
+            .iter()
+            .map(|&s| (s as f32 - self.mean).powi(2))
+            .sum::<f32>()
+            / n as f32;
+        self.stddev = variance.sqrt();
+        self
+    }
+
+    // Column names and values, the same for every format
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("generation", self.generation.to_string()),
+            ("best", self.best.to_string()),
+            ("mean", self.mean.to_string()),
+            ("median", self.median.to_string()),
+            ("stddev", self.stddev.to_string()),
+            ("pipes", self.pipes.to_string()),
+            ("level", self.level.to_string()),
+            ("diversity", self.diversity.to_string()),
+            ("genotype_diversity", self.genotype_diversity.to_string()),
+            ("behaviour_diversity", self.behaviour_diversity.to_string()),
+            ("stagnant", self.stagnant.to_string()),
+            ("seconds", self.seconds.to_string()),
+            ("seed", self.seed.to_string()),
+            ("population", self.population.to_string()),
+            ("hidden_nodes", self.hidden_nodes.to_string()),
+            ("mutation_rate", self.mutation_rate.to_string()),
+            ("mutation_size", self.mutation_size.to_string()),
+            ("frames", self.frames.to_string()),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Csv,
+    JsonLines,
+}
+
+/**
+ * Writes a line per generation as it finishes, so a run can be
+ * followed or plotted while it's still going
+ */
+#[derive(Debug)]
+pub struct Stats {
+    path: String,
+    format: Format,
+    file: File,
+}
+
+impl Stats {
+    // Starts a new .csv or .jsonl file at path
+    pub fn create(path: &str) -> Result<Stats, String> {
+        let format = if path.ends_with(".csv") {
+            Format::Csv
+        } else if path.ends_with(".jsonl") {
+            Format::JsonLines
+        } else {
+            return Err(format!(
+                "Unknown stats format for {}, use .csv or .jsonl",
+                path
+            ));
+        };
+        let file = File::create(path).map_err(|e| format!("Unable to write {}: {}", path, e))?;
+        let mut stats = Stats {
+            path: path.to_string(),
+            format,
+            file,
+        };
+        if format == Format::Csv {
+            let fields = GenerationStats::default().fields();
+            let names: Vec<&str> = fields.iter().map(|(name, _)| *name).collect();
+            stats.write(&names.join(","))?;
+        }
+        Ok(stats)
+    }
+
+    pub fn log(&mut self, stats: &GenerationStats) -> Result<(), String> {
+        let fields = stats.fields();
+        let line = match self.format {
+            Format::Csv => {
+                let values: Vec<&str> = fields.iter().map(|(_, v)| v.as_str()).collect();
+                values.join(",")
+            }
+            Format::JsonLines => {
+                let pairs: Vec<String> = fields
+                    .iter()
+                    .map(|(name, value)| format!("\"{}\": {}", name, value))
+                    .collect();
+                format!("{{{}}}", pairs.join(", "))
+            }
+        };
+        self.write(&line)
+    }
+
+    fn write(&mut self, line: &str) -> Result<(), String> {
+        writeln!(self.file, "{}", line).map_err(|e| format!("Unable to write {}: {}", self.path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summarizes_scores() {
+        let stats = GenerationStats::default().with_scores(&[4, 1, 3, 2]);
+        assert_eq!(stats.best, 4);
+        assert_eq!(stats.mean, 2.5);
+        assert_eq!(stats.median, 2.5);
+        assert!((stats.stddev - 1.118).abs() < 0.001);
+    }
+}
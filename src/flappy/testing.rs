@@ -0,0 +1,19 @@
+use crate::flappy::bird::Bird;
+use crate::flappy::generator::PipeGenerator;
+use crate::flappy::pipe::PipeConfig;
+use crate::nn::nn::NeuralNetwork;
+
+// An untrained bird with the five classic inputs and a flap and a wait output
+pub fn bird() -> Bird {
+    bird_with(2)
+}
+
+// The same with a brain of outputs outputs
+pub fn bird_with(outputs: i32) -> Bird {
+    Bird::new(800, 600, NeuralNetwork::new(5, 8, outputs))
+}
+
+// The classic course the tests fly, the seed of every flight picks its pipes
+pub fn course() -> PipeGenerator {
+    PipeGenerator::new(800, 600, 0, PipeConfig::default())
+}
@@ -0,0 +1,379 @@
+use crate::flappy::bird::Bird;
+use crate::flappy::pipe::Pipe;
+use crate::renderer::sprite::Sprite;
+use crate::renderer::target::DrawTarget;
+use sdl2::pixels::Color;
+use sdl2::rect::{Point, Rect};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+const WHITE: Color = Color::RGB(255, 255, 255);
+
+// A background image scrolling at a fraction of the pipe speed
+#[derive(Debug, Clone)]
+pub struct Layer {
+    pub sprite: Sprite,
+    pub speed: f32,
+    // Distance from the top, at the bottom of the screen if None
+    pub top: Option<i32>,
+}
+
+/**
+ * Colors and sprites the game is drawn with. Anything without a
+ * sprite falls back to plain shapes in the theme colors, so the
+ * classic theme is the original white on black look
+ */
+#[derive(Debug, Clone)]
+pub struct Theme {
+    pub background: Color,
+    pub pipe: Color,
+    pub bird: Color,
+    pub ghost: Color,
+    pub player: Color,
+    pub text: Color,
+    // Highlight around the best bird
+    pub best: Color,
+    pub bird_sprite: Option<Sprite>,
+    pub pipe_sprite: Option<Sprite>,
+    // Drawn back to front
+    pub layers: Vec<Layer>,
+}
+
+impl Theme {
+    pub fn classic() -> Theme {
+        Theme {
+            background: Color::RGB(0, 0, 0),
+            pipe: Color::RGB(255, 255, 255),
+            bird: Color::RGB(255, 255, 255),
+            ghost: Color::RGB(90, 90, 90),
+            player: Color::RGB(255, 200, 0),
+            text: Color::RGB(255, 255, 255),
+            best: Color::RGB(255, 60, 60),
+            bird_sprite: None,
+            pipe_sprite: None,
+            layers: Vec::new(),
+        }
+    }
+
+    /**
+     * Reads a theme file, one setting per line on top of the
+     * classic theme:
+     *   pipe 40 160 60
+     *   bird-sprite sprites/bird.png
+     *   layer sprites/hills.png 0.5
+     *   layer sprites/clouds.png 0.1 40
+     * Sprite paths are relative to the theme file. A sprite that
+     * fails to load fails the whole theme, rather than quietly
+     * drawing shapes where sprites were asked for
+     */
+    pub fn load(path: &str) -> Result<Theme, String> {
+        let text =
+            fs::read_to_string(path).map_err(|e| format!("Unable to read {}: {}", path, e))?;
+        let dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+        Theme::parse(&text, path, |file| {
+            Sprite::load(&dir.join(file).to_string_lossy())
+        })
+    }
+
+    // The settings in text, loading sprites with sprite, path only names the file in errors
+    fn parse<F>(text: &str, path: &str, sprite: F) -> Result<Theme, String>
+    where
+        F: Fn(&str) -> Result<Sprite, String>,
+    {
+        let bad = |line: &str| format!("Malformed line in {}: {}", path, line);
+
+        let mut theme = Theme::classic();
+        for line in text.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let color = |r: &str, g: &str, b: &str| -> Result<Color, String> {
+                let channel = |c: &str| c.parse::<u8>().map_err(|_| bad(line));
+                Ok(Color::RGB(channel(r)?, channel(g)?, channel(b)?))
+            };
+            match fields.as_slice() {
+                ["background", r, g, b] => theme.background = color(r, g, b)?,
+                ["pipe", r, g, b] => theme.pipe = color(r, g, b)?,
+                ["bird", r, g, b] => theme.bird = color(r, g, b)?,
+                ["ghost", r, g, b] => theme.ghost = color(r, g, b)?,
+                ["player", r, g, b] => theme.player = color(r, g, b)?,
+                ["text", r, g, b] => theme.text = color(r, g, b)?,
+                ["best", r, g, b] => theme.best = color(r, g, b)?,
+                ["bird-sprite", file] => theme.bird_sprite = Some(sprite(file)?),
+                ["pipe-sprite", file] => theme.pipe_sprite = Some(sprite(file)?),
+                ["layer", file, speed, top @ ..] if top.len() <= 1 => {
+                    let speed = speed.parse().map_err(|_| bad(line))?;
+                    let top = match top.first() {
+                        Some(top) => Some(top.parse().map_err(|_| bad(line))?),
+                        None => None,
+                    };
+                    let sprite = sprite(file)?;
+                    theme.layers.push(Layer { sprite, speed, top });
+                }
+                [first, ..] if first.starts_with('#') => {}
+                [] => {}
+                _ => return Err(bad(line)),
+            }
+        }
+        Ok(theme)
+    }
+
+    /**
+     * Clears to the background color and tiles every layer across
+     * the screen. scrolled is how many pixels the pipes moved so
+     * far, layers with a lower speed look further away
+     */
+    pub fn background(&self, canvas: &mut dyn DrawTarget, scrolled: f32) -> Result<(), String> {
+        canvas.set_color(self.background);
+        canvas.clear()?;
+        let (width, height) = canvas.size();
+        for layer in self.layers.iter() {
+            let (w, h) = (layer.sprite.width(), layer.sprite.height());
+            let top = layer.top.unwrap_or(height as i32 - h as i32);
+            let offset = (scrolled * layer.speed) % w as f32;
+            let mut x = -offset as i32;
+            while x < width as i32 {
+                canvas.sprite(&layer.sprite, Rect::new(x, top, w, h), 0.0, false, WHITE)?;
+                x += w as i32;
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * The pipe sprite is the bottom half with its opening at the
+     * top, the top half is the same sprite upside down. It keeps
+     * its proportions unless that leaves the pipe too short
+     */
+    pub fn pipe(&self, canvas: &mut dyn DrawTarget, pipe: &Pipe, alpha: f32) -> Result<(), String> {
+        let sprite = match &self.pipe_sprite {
+            Some(sprite) => sprite,
+            None => {
+                canvas.set_color(self.pipe);
+                pipe.show(canvas, alpha);
+                return Ok(());
+            }
+        };
+        let w = pipe.w as u32;
+        let natural = sprite.height() * w / sprite.width();
+        for column in pipe.columns() {
+            let x = (column.prev_x + (column.x - column.prev_x) * alpha) as i32;
+            let top_h = natural.max(column.top as u32);
+            let top = Rect::new(x, column.top as i32 - top_h as i32, w, top_h);
+            canvas.sprite(sprite, top, 0.0, true, WHITE)?;
+            let bottom_y = canvas.size().1 as i32 - column.bottom as i32;
+            let bottom = Rect::new(x, bottom_y, w, natural.max(column.bottom as u32));
+            canvas.sprite(sprite, bottom, 0.0, false, WHITE)?;
+        }
+        Ok(())
+    }
+
+    // A single bird, tilted with its velocity when it has a sprite
+    pub fn bird(
+        &self,
+        canvas: &mut dyn DrawTarget,
+        bird: &Bird,
+        color: Color,
+        alpha: f32,
+    ) -> Result<(), String> {
+        match &self.bird_sprite {
+            Some(sprite) => canvas.sprite(
+                sprite,
+                bird_rect(sprite, bird, alpha),
+                bird.tilt(),
+                false,
+                WHITE,
+            ),
+            None => {
+                canvas.set_color(color);
+                bird.show_smooth(canvas, alpha);
+                canvas.fill_polygon(&beak(bird, alpha))
+            }
+        }
+    }
+
+    // Many birds at once, the shapes go out in a single batch
+    pub fn birds<'a, I>(
+        &self,
+        canvas: &mut dyn DrawTarget,
+        birds: I,
+        color: Color,
+        alpha: f32,
+    ) -> Result<(), String>
+    where
+        I: Iterator<Item = &'a Bird>,
+    {
+        match &self.bird_sprite {
+            Some(sprite) => {
+                for bird in birds {
+                    let dst = bird_rect(sprite, bird, alpha);
+                    canvas.sprite(sprite, dst, bird.tilt(), false, WHITE)?;
+                }
+                Ok(())
+            }
+            None => {
+                let circles: Vec<(Point, i32)> = birds
+                    .map(|bird| {
+                        let (x, y) = bird.center(alpha);
+                        (Point::new(x as i32, y as i32), bird.r as i32)
+                    })
+                    .collect();
+                canvas.set_color(color);
+                canvas.fill_circles(&circles)
+            }
+        }
+    }
+
+    // Birds in their own colors, sprites get tinted with them
+    pub fn colored_birds(
+        &self,
+        canvas: &mut dyn DrawTarget,
+        birds: &[(&Bird, Color)],
+        alpha: f32,
+    ) -> Result<(), String> {
+        if let Some(sprite) = &self.bird_sprite {
+            for &(bird, color) in birds.iter() {
+                let dst = bird_rect(sprite, bird, alpha);
+                canvas.sprite(sprite, dst, bird.tilt(), false, color)?;
+            }
+            return Ok(());
+        }
+        // One batch per color, always in the same order
+        let mut batches: BTreeMap<(u8, u8, u8), Vec<(Point, i32)>> = BTreeMap::new();
+        for &(bird, color) in birds.iter() {
+            let (x, y) = bird.center(alpha);
+            batches
+                .entry((color.r, color.g, color.b))
+                .or_default()
+                .push((Point::new(x as i32, y as i32), bird.r as i32));
+        }
+        for ((r, g, b), circles) in batches.iter() {
+            canvas.set_color(Color::RGB(*r, *g, *b));
+            canvas.fill_circles(circles)?;
+        }
+        Ok(())
+    }
+
+    // The bird to watch, drawn on top with a ring around it
+    pub fn highlight(
+        &self,
+        canvas: &mut dyn DrawTarget,
+        bird: &Bird,
+        alpha: f32,
+    ) -> Result<(), String> {
+        self.bird(canvas, bird, self.best, alpha)?;
+        let (x, y) = bird.center(alpha);
+        let ring = bird.r as i32 + 4;
+        canvas.set_color(self.best);
+        // A crisp ring with a soft outer edge
+        canvas.circle(Point::new(x as i32, y as i32), ring)?;
+        canvas.circle_aa((x, y), (ring + 1) as f32)
+    }
+}
+
+// Families get hues spread around the color wheel
+pub fn lineage_color(lineage: u32) -> Color {
+    hsv((lineage as f32 * 0.618_034).fract() * 360.0, 0.75, 1.0)
+}
+
+// Green for the top of the ranking through yellow to red, rank
+// goes from 0 for the best to 1 for the worst
+pub fn rank_color(rank: f32) -> Color {
+    hsv(120.0 * (1.0 - rank.clamp(0.0, 1.0)), 0.85, 1.0)
+}
+
+fn hsv(hue: f32, saturation: f32, value: f32) -> Color {
+    let c = value * saturation;
+    let x = c * (1.0 - ((hue / 60.0) % 2.0 - 1.0).abs());
+    let (r, g, b) = match (hue / 60.0) as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let channel = |v: f32| ((v + value - c) * 255.0) as u8;
+    Color::RGB(channel(r), channel(g), channel(b))
+}
+
+// Triangle in front of a bird without a sprite, tilted like a sprite would be
+fn beak(bird: &Bird, alpha: f32) -> Vec<Point> {
+    let (x, y) = bird.center(alpha);
+    let (sin, cos) = bird.tilt().to_radians().sin_cos();
+    let r = bird.r;
+    // Along the way the bird faces and across it
+    let at = |along: f32, across: f32| {
+        Point::new(
+            (x + along * cos - across * sin) as i32,
+            (y + along * sin + across * cos) as i32,
+        )
+    };
+    vec![
+        at(r * 0.6, -r * 0.4),
+        at(r * 1.5, 0.0),
+        at(r * 0.6, r * 0.4),
+    ]
+}
+
+// As tall as the bird's circle, centered on it
+fn bird_rect(sprite: &Sprite, bird: &Bird, alpha: f32) -> Rect {
+    let (x, y) = bird.center(alpha);
+    let h = bird.r * 2.0;
+    let w = h * sprite.width() as f32 / sprite.height() as f32;
+    Rect::new(
+        (x - w / 2.0) as i32,
+        (y - h / 2.0) as i32,
+        w as u32,
+        h as u32,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Result<Theme, String> {
+        Theme::parse(text, "test.theme", |file| match file {
+            "missing.png" => Err(format!("Unable to load {}", file)),
+            _ => Ok(Sprite::new(2, 1, vec![255; 8])),
+        })
+    }
+
+    #[test]
+    fn reads_colors_on_top_of_the_classic_theme() {
+        let theme = parse("# Night\nbackground 16 18 40\n\n  pipe 60 90 160\n").unwrap();
+        assert_eq!(theme.background, Color::RGB(16, 18, 40));
+        assert_eq!(theme.pipe, Color::RGB(60, 90, 160));
+        assert_eq!(theme.bird, Theme::classic().bird);
+        assert!(theme.bird_sprite.is_none() && theme.layers.is_empty());
+    }
+
+    #[test]
+    fn reads_sprites_and_layers() {
+        let theme =
+            parse("bird-sprite bird.png\nlayer hills.png 0.4\nlayer clouds.png 0.05 40").unwrap();
+        assert!(theme.bird_sprite.is_some());
+        assert_eq!(theme.layers.len(), 2);
+        assert_eq!((theme.layers[0].speed, theme.layers[0].top), (0.4, None));
+        assert_eq!(
+            (theme.layers[1].speed, theme.layers[1].top),
+            (0.05, Some(40))
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_lines_and_missing_sprites() {
+        for line in [
+            "pipe 60 90",
+            "pipe 60 90 300",
+            "layer hills.png fast",
+            "layer hills.png 0.4 40 2",
+            "sky 1 2 3",
+            "pipe-sprite missing.png",
+        ]
+        .iter()
+        {
+            assert!(parse(line).is_err(), "{}", line);
+        }
+    }
+}
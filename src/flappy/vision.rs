@@ -0,0 +1,125 @@
+use crate::flappy::bird::Bird;
+use crate::flappy::pipe::Pipe;
+use crate::renderer::target::DrawTarget;
+
+// Rays a bird sees with when nothing else is asked for
+pub const DEFAULT_RAYS: u32 = 5;
+// Most rays a bird can have
+pub const MAX_RAYS: u32 = 64;
+// Degrees between the top and bottom ray, centered on straight ahead
+const FIELD_OF_VIEW: f32 = 120.0;
+
+// What one ray ran into
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray {
+    // Degrees from straight ahead, up is negative
+    pub angle: f32,
+    // Pixels to the first pipe, ceiling or floor, at most the range
+    pub distance: f32,
+    // Distance as a fraction of the range, for the brain
+    pub seen: f32,
+}
+
+// Directions of rays spread evenly across the field of view
+pub fn angles(rays: u32) -> Vec<f32> {
+    match rays {
+        0 => Vec::new(),
+        1 => vec![0.0],
+        _ => {
+            let step = FIELD_OF_VIEW / (rays - 1) as f32;
+            (0..rays)
+                .map(|i| -FIELD_OF_VIEW / 2.0 + step * i as f32)
+                .collect()
+        }
+    }
+}
+
+/**
+ * Distance from the bird to the first pipe, ceiling or floor in
+ * the direction angle degrees from straight ahead, at most range.
+ * Pipes decide for themselves what a ray hits, so any kind of
+ * pipe can be seen
+ */
+pub fn cast(bird: &Bird, pipes: &[Pipe], angle: f32, range: f32) -> f32 {
+    let radians = angle.to_radians();
+    let dir = (radians.cos(), radians.sin());
+    let origin = (bird.x, bird.y);
+    let height = bird.height() as f32;
+    let mut closest = range;
+    // Ceiling and floor
+    if dir.1 < 0.0 {
+        closest = closest.min(-bird.y / dir.1);
+    } else if dir.1 > 0.0 {
+        closest = closest.min((height - bird.y) / dir.1);
+    }
+    for pipe in pipes.iter() {
+        if let Some(t) = pipe.raycast(origin, dir) {
+            closest = closest.min(t);
+        }
+    }
+    closest.max(0.0)
+}
+
+// Everything the bird sees with rays rays, as far as the screen is wide
+pub fn look(bird: &Bird, pipes: &[Pipe], rays: u32) -> Vec<Ray> {
+    let range = bird.width() as f32;
+    angles(rays)
+        .into_iter()
+        .map(|angle| {
+            let distance = cast(bird, pipes, angle, range);
+            Ray {
+                angle,
+                distance,
+                seen: distance / range,
+            }
+        })
+        .collect()
+}
+
+// Debug lines from the bird to whatever every ray hit
+pub fn show(
+    canvas: &mut dyn DrawTarget,
+    bird: &Bird,
+    pipes: &[Pipe],
+    rays: u32,
+    alpha: f32,
+) -> Result<(), String> {
+    let (x, y) = bird.center(alpha);
+    for ray in look(bird, pipes, rays) {
+        let radians = ray.angle.to_radians();
+        let end = (
+            x + radians.cos() * ray.distance,
+            y + radians.sin() * ray.distance,
+        );
+        canvas.line_aa((x, y), end)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flappy::pipe::{PipeConfig, Variant};
+    use crate::flappy::testing;
+
+    #[test]
+    fn rays_spread_across_the_field_of_view() {
+        assert_eq!(angles(5), vec![-60.0, -30.0, 0.0, 30.0, 60.0]);
+        assert_eq!(angles(1), vec![0.0]);
+    }
+
+    #[test]
+    fn rays_stop_at_pipes_and_bounds() {
+        let bird = testing::bird();
+        // Straight ahead through the gap, into the top half further up
+        let mut pipe = Pipe::shaped(800, 600, 300.0, &PipeConfig::default(), Variant::Static);
+        pipe.x = 200.0;
+        assert_eq!(cast(&bird, &[pipe.clone()], 0.0, 800.0), 800.0);
+        pipe.top = 350.0;
+        assert!((cast(&bird, &[pipe.clone()], 0.0, 800.0) - 136.0).abs() < 0.01);
+        // Straight down from the middle
+        assert!((cast(&bird, &[], 90.0, 800.0) - 300.0).abs() < 0.01);
+        let seen = look(&bird, &[pipe], 1);
+        assert!((seen[0].seen - 0.17).abs() < 0.01);
+    }
+}
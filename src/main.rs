@@ -7,17 +7,422 @@ mod matrix;
 mod nn;
 mod renderer;
 
-use std::time::Duration;
+use std::path::Path;
+use std::time::{Duration, Instant};
 
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
+use sdl2::pixels::PixelFormatEnum;
 use sdl2::rect::Rect;
+use sdl2::sys::SDL_RendererFlags;
 
-use flappy::game::Game;
+use flappy::action::ActionHead;
+use flappy::benchmark::Report;
+use flappy::bird::Bird;
+use flappy::capture::capture;
+use flappy::dqn::{self, Dqn, ReplayBuffer};
+use flappy::env::{ObservationSpace, Reward};
+use flappy::episode::{Aggregate, Evaluation};
+use flappy::game::{Coloring, Game, Reaction, HIDDEN_NODES};
+use flappy::generator::{Curriculum, PipeGenerator};
+use flappy::normalize;
+use flappy::physics::{Impulse, PhysicsConfig};
+use flappy::pipe::{PipeConfig, Variants};
+use flappy::play::Play;
+use flappy::recording::{Recording, Replay};
+use flappy::reinforce::{self, Reinforce};
+use flappy::sensors::SensorSet;
+use flappy::stats::{GenerationStats, Stats};
+use flappy::theme::Theme;
+use nn::nn::NeuralNetwork;
+use renderer::png;
+use renderer::renderer::Renderer;
+use renderer::target::DrawTarget;
+
+// What is running in the window
+enum Mode {
+    // The neural networks learn to play
+    Train(Game),
+    // A human plays with the spacebar
+    Play(Play),
+    // A recorded run is played back
+    Replay(Replay),
+}
+
+impl Mode {
+    // How many simulation steps make one timestep
+    fn steps_per_tick(&self) -> i32 {
+        match self {
+            Mode::Train(game) => game.cycle_speed(),
+            _ => 1,
+        }
+    }
+
+    fn update(&mut self) {
+        match self {
+            Mode::Train(game) => game.update(),
+            Mode::Play(play) => play.update(),
+            Mode::Replay(replay) => {
+                replay.step();
+            }
+        }
+    }
+
+    fn draw(
+        &mut self,
+        canvas: &mut dyn DrawTarget,
+        theme: &Theme,
+        alpha: f32,
+    ) -> Result<(), String> {
+        match self {
+            Mode::Train(game) => game.draw(canvas, theme, alpha),
+            Mode::Play(play) => play.draw(canvas, theme, alpha),
+            Mode::Replay(replay) => replay.draw(canvas, theme, alpha),
+        }
+    }
+}
+
+fn usage() -> String {
+    String::from(
+        "Usage: rusty-birds [train] [--record <dir>] [--colors plain|lineage|rank]
+                          [--genealogy <file.dot|file.json>] [--ancestry <file.dot|file.json>]
+                          [--stats <file.csv|file.jsonl>] [--save-brain <file>]
+                          [--stagnation <generations> [--on-stagnation mutate|immigrants|restart]]
+                          [--episodes <n> [--same-seeds] [--quantile <0..1>]]
+                          [--curriculum generations|score] [--sensors <list>]
+                          [--standardize] [--input-stats]
+                          [--action argmax|threshold[:t]|continuous|stochastic]
+       rusty-birds play [ghosts] [--ghost-brain <file> [--sensors <list>] [--action <head>]]
+                        [--record <dir>]
+       rusty-birds replay <recording> [--headless]
+       rusty-birds export <recording> <pairs.csv>
+       rusty-birds reinforce [--updates <n>] [--batch <episodes>] [--learning-rate <x>]
+                             [--discount <0..1>] [--stats <file>] [--save-brain <file>]
+                             [--sensors <list>]
+       rusty-birds dqn [--updates <n>] [--episodes <n>] [--batch <transitions>]
+                       [--learning-rate <x>] [--discount <0..1>] [--epsilon-decay <frames>]
+                       [--sync-every <frames>] [--buffer <transitions>]
+                       [--stats <file>] [--save-brain <file>] [--sensors <list>]
+       rusty-birds evaluate <brain> [--sensors <list>] [--action <head>]
+       rusty-birds capture <recording> <out.png|out.svg|out.gif|out.rgba> [--frame <n>] [--every <n>]
+Anything drawn takes --theme <file> to change colors and sprites
+Training, reinforce, dqn and playing take --gap, --pipe-speed, --pipe-width, --pipe-interval
+and --variants oscillating,closing,staggered,wide
+Training, reinforce, dqn, playing and evaluate take --physics original|classic|floaty|heavy,
+changed by --gravity, --lift, --impulse add|set, --terminal-velocity, --drag
+and --flap-cooldown
+Reinforce and dqn take --reward shaped|frames|pipes and --observation inputs|raw",
+    )
+}
+
+// Value given to a flag, like --record runs
+fn flag<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    let i = args.iter().position(|a| a == name)?;
+    args.get(i + 1).map(|a| a.as_str())
+}
+
+// Classic pipes changed by --gap, --pipe-speed, --pipe-width, --pipe-interval and --variants
+fn pipe_config(args: &[String]) -> Result<PipeConfig, String> {
+    let mut config = PipeConfig::default();
+    if let Some(gap) = flag(args, "--gap") {
+        config.gap = gap.parse().map_err(|_| usage())?;
+    }
+    if let Some(speed) = flag(args, "--pipe-speed") {
+        config.speed = speed.parse().map_err(|_| usage())?;
+    }
+    if let Some(width) = flag(args, "--pipe-width") {
+        config.width = width.parse().map_err(|_| usage())?;
+    }
+    if let Some(interval) = flag(args, "--pipe-interval") {
+        config.interval = interval.parse().map_err(|_| usage())?;
+    }
+    if let Some(variants) = flag(args, "--variants") {
+        config.variants = Variants::parse(variants)?;
+    }
+    Ok(config)
+}
+
+// A --physics preset changed by --gravity, --lift, --impulse, --terminal-velocity,
+// --drag and --flap-cooldown
+fn physics(args: &[String]) -> Result<PhysicsConfig, String> {
+    let mut physics = match flag(args, "--physics") {
+        Some(preset) => PhysicsConfig::parse(preset)?,
+        None => PhysicsConfig::default(),
+    };
+    if let Some(gravity) = flag(args, "--gravity") {
+        physics.gravity = gravity.parse().map_err(|_| usage())?;
+    }
+    if let Some(lift) = flag(args, "--lift") {
+        physics.lift = lift.parse().map_err(|_| usage())?;
+    }
+    if let Some(impulse) = flag(args, "--impulse") {
+        physics.impulse = Impulse::parse(impulse)?;
+    }
+    if let Some(terminal) = flag(args, "--terminal-velocity") {
+        physics.terminal_velocity = terminal.parse().map_err(|_| usage())?;
+    }
+    if let Some(drag) = flag(args, "--drag") {
+        physics.drag = drag.parse().map_err(|_| usage())?;
+    }
+    if let Some(cooldown) = flag(args, "--flap-cooldown") {
+        physics.cooldown = cooldown.parse().map_err(|_| usage())?;
+    }
+    Ok(physics)
+}
+
+// Value of a numeric flag, or default when it's not given
+fn number<T: std::str::FromStr>(args: &[String], name: &str, default: T) -> Result<T, String> {
+    match flag(args, name) {
+        Some(n) => n.parse().map_err(|_| usage()),
+        None => Ok(default),
+    }
+}
+
+// A new bird with outputs outputs for --sensors and --physics, and the course of the pipe flags
+fn learner(
+    args: &[String],
+    width: u32,
+    height: u32,
+    outputs: i32,
+) -> Result<(Bird, PipeGenerator), String> {
+    let sensors = match flag(args, "--sensors") {
+        Some(sensors) => SensorSet::parse(sensors)?,
+        None => SensorSet::default(),
+    };
+    let brain = NeuralNetwork::new(sensors.size() as i32, HIDDEN_NODES, outputs);
+    let mut bird = Bird::new(width, height, brain);
+    bird.set_sensors(sensors);
+    bird.set_physics(physics(args)?);
+    let pipes = pipe_config(args)?;
+    bird.set_pipe_config(pipes);
+    let course = PipeGenerator::new(width, height, rand::random(), pipes);
+    Ok((bird, course))
+}
+
+/**
+ * Runs --updates updates of a learner without a window. Stats
+ * have the same columns as training's, so the frames it took
+ * can be compared
+ */
+fn learn<F>(args: &[String], mut update: F) -> Result<(), String>
+where
+    F: FnMut() -> Result<GenerationStats, String>,
+{
+    let mut stats = flag(args, "--stats").map(Stats::create).transpose()?;
+    for _ in 0..number(args, "--updates", 500)? {
+        let record = update()?;
+        if let Some(stats) = stats.as_mut() {
+            stats.log(&record)?;
+        }
+        if record.generation % 10 == 1 {
+            println!(
+                "Update {}: mean {:.1} frames, best {}, {} frames flown",
+                record.generation, record.mean, record.best, record.frames
+            );
+        }
+    }
+    Ok(())
+}
+
+// Trains a single stochastic policy with REINFORCE instead of breeding birds
+fn reinforce(args: &[String], width: u32, height: u32) -> Result<(), String> {
+    let (bird, course) = learner(args, width, height, 1)?;
+    let mut trainer = Reinforce::new(bird, course, rand::random())?;
+    trainer.batch = number(args, "--batch", trainer.batch)?;
+    trainer.discount = number(args, "--discount", trainer.discount)?;
+    if let Some(reward) = flag(args, "--reward") {
+        trainer.reward = Reward::parse(reward)?;
+    }
+    if let Some(space) = flag(args, "--observation") {
+        trainer.space = ObservationSpace::parse(space)?;
+    }
+    let rate = number(args, "--learning-rate", reinforce::LEARNING_RATE)?;
+    trainer.bird.brain.set_learning_rate(rate);
+    learn(args, || trainer.update())?;
+    if let Some(path) = flag(args, "--save-brain") {
+        trainer.bird.brain.save(path)?;
+        println!("Saved {}, evaluate it with --action stochastic", path);
+    }
+    Ok(())
+}
+
+// Trains a single brain with deep Q-learning instead of breeding birds
+fn dqn(args: &[String], width: u32, height: u32) -> Result<(), String> {
+    let (bird, course) = learner(args, width, height, 2)?;
+    let mut trainer = Dqn::new(bird, course, rand::random())?;
+    trainer.episodes = number(args, "--episodes", trainer.episodes)?;
+    trainer.batch = number(args, "--batch", trainer.batch)?;
+    trainer.discount = number(args, "--discount", trainer.discount)?;
+    if let Some(reward) = flag(args, "--reward") {
+        trainer.reward = Reward::parse(reward)?;
+    }
+    if let Some(space) = flag(args, "--observation") {
+        trainer.space = ObservationSpace::parse(space)?;
+    }
+    trainer.epsilon_decay = number(args, "--epsilon-decay", trainer.epsilon_decay)?;
+    trainer.sync_every = number(args, "--sync-every", trainer.sync_every)?;
+    trainer.buffer = ReplayBuffer::new(number(args, "--buffer", 50_000)?);
+    let rate = number(args, "--learning-rate", dqn::LEARNING_RATE)?;
+    trainer.bird.brain.set_learning_rate(rate);
+    learn(args, || trainer.update())?;
+    if let Some(path) = flag(args, "--save-brain") {
+        trainer.bird.brain.save(path)?;
+        println!("Saved {}", path);
+    }
+    Ok(())
+}
+
+// A bird flying the brain saved at path, with --sensors and --action
+fn trained_bird(args: &[String], path: &str, width: u32, height: u32) -> Result<Bird, String> {
+    let brain = NeuralNetwork::load(path)?;
+    let sensors = match flag(args, "--sensors") {
+        Some(sensors) => SensorSet::parse(sensors)?,
+        None => SensorSet::default(),
+    };
+    let mut bird = Bird::new(width, height, brain);
+    bird.set_sensors(sensors);
+    // Brains trained with --standardize have their statistics next to them
+    let stats = normalize::stats_path(path);
+    if Path::new(&stats).exists() {
+        bird.normalizer.load(&stats)?;
+    }
+    if let Some(head) = flag(args, "--action") {
+        bird.head = ActionHead::parse(head)?;
+    }
+    bird.check_brain()?;
+    Ok(bird)
+}
+
+// Seconds since the epoch, to name files
+fn timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
 
 fn main() -> Result<(), String> {
-    let width: u32 = 800;
-    let height: u32 = 600;
+    let mut width: u32 = 800;
+    let mut height: u32 = 600;
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let positional = |i: usize| args.get(i).filter(|a| !a.starts_with("--"));
+    let theme = match flag(&args, "--theme") {
+        Some(path) => Theme::load(path)?,
+        None => Theme::classic(),
+    };
+    // Training is the default, flags can come first
+    let command = args.first().map(|a| a.as_str());
+    let mut mode = match command.filter(|a| !a.starts_with("--")) {
+        None | Some("train") => {
+            let mut game = Game::new(width, height);
+            if let Some(dir) = flag(&args, "--record") {
+                game.record_to(dir)?;
+            }
+            if let Some(coloring) = flag(&args, "--colors") {
+                game.set_coloring(Coloring::parse(coloring)?);
+            }
+            if let Some(path) = flag(&args, "--genealogy") {
+                game.export_genealogy_to(path);
+            }
+            if let Some(path) = flag(&args, "--ancestry") {
+                game.export_ancestry_to(path);
+            }
+            if let Some(path) = flag(&args, "--save-brain") {
+                game.save_brain_to(path);
+            }
+            if let Some(path) = flag(&args, "--stats") {
+                game.log_stats_to(path)?;
+            }
+            if let Some(generations) = flag(&args, "--stagnation") {
+                let generations = generations.parse().map_err(|_| usage())?;
+                let reaction = flag(&args, "--on-stagnation").unwrap_or("mutate");
+                game.on_stagnation(generations, Reaction::parse(reaction)?);
+            }
+            if let Some(episodes) = flag(&args, "--episodes") {
+                let mut evaluation = Evaluation::new(episodes.parse().map_err(|_| usage())?);
+                evaluation.same_seeds = args.iter().any(|a| a == "--same-seeds");
+                if let Some(q) = flag(&args, "--quantile") {
+                    evaluation.aggregate = Aggregate::Quantile(q.parse().map_err(|_| usage())?);
+                }
+                game.evaluate_with(evaluation);
+            }
+            game.set_pipe_config(pipe_config(&args)?);
+            game.set_physics(physics(&args)?);
+            if let Some(sensors) = flag(&args, "--sensors") {
+                game.set_sensors(SensorSet::parse(sensors)?);
+            }
+            if let Some(head) = flag(&args, "--action") {
+                game.set_action_head(ActionHead::parse(head)?);
+            }
+            if args.iter().any(|a| a == "--standardize") {
+                game.standardize();
+            }
+            game.show_input_stats(args.iter().any(|a| a == "--input-stats"));
+            if let Some(curriculum) = flag(&args, "--curriculum") {
+                game.set_curriculum(Some(Curriculum::parse(curriculum)?));
+            }
+            Mode::Train(game)
+        }
+        Some("play") => {
+            let brain = flag(&args, "--ghost-brain");
+            // A trained brain flies as one ghost unless asked for more
+            let ghosts = match positional(1) {
+                Some(n) => n.parse().map_err(|_| usage())?,
+                None => brain.is_some() as usize,
+            };
+            let mut play = Play::new(width, height, ghosts);
+            if let Some(path) = brain {
+                play.set_ghosts(&trained_bird(&args, path, width, height)?);
+            }
+            if let Some(dir) = flag(&args, "--record") {
+                play.record_to(dir)?;
+            }
+            play.set_pipe_config(pipe_config(&args)?);
+            play.set_physics(physics(&args)?);
+            Mode::Play(play)
+        }
+        Some("replay") => {
+            let recording = Recording::load(positional(1).ok_or_else(usage)?)?;
+            width = recording.width;
+            height = recording.height;
+            let mut replay = Replay::new(recording);
+            if args.iter().any(|a| a == "--headless") {
+                while replay.step() {}
+                println!("Frames: {}", replay.frame());
+                println!("Pipes passed: {}", replay.pipes_passed());
+                return Ok(());
+            }
+            Mode::Replay(replay)
+        }
+        Some("export") => {
+            let recording = Recording::load(positional(1).ok_or_else(usage)?)?;
+            let out = positional(2).ok_or_else(usage)?;
+            let pairs = recording.export_training_pairs(out)?;
+            println!("Exported {} training pairs to {}", pairs, out);
+            return Ok(());
+        }
+        Some("reinforce") => return reinforce(&args, width, height),
+        Some("dqn") => return dqn(&args, width, height),
+        Some("evaluate") => {
+            let path = positional(1).ok_or_else(usage)?;
+            let mut bird = trained_bird(&args, path, width, height)?;
+            bird.set_physics(physics(&args)?);
+            let report = Report::run(&bird)?;
+            print!("{}", report.summary());
+            return Ok(());
+        }
+        Some("capture") => {
+            let recording = Recording::load(positional(1).ok_or_else(usage)?)?;
+            let out = positional(2).ok_or_else(usage)?;
+            let number = |name| flag(&args, name).map(|n| n.parse().map_err(|_| usage()));
+            let frame = number("--frame").transpose()?;
+            let every = number("--every").transpose()?.unwrap_or(2);
+            println!("{}", capture(recording, &theme, out, frame, every)?);
+            return Ok(());
+        }
+        Some(_) => return Err(usage()),
+    };
 
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
@@ -36,6 +441,7 @@ fn main() -> Result<(), String> {
     let mut canvas = window
         .into_canvas()
         .accelerated()
+        .present_vsync()
         .build()
         .map_err(|e| e.to_string())?;
     canvas.set_draw_color(sdl2::pixels::Color::RGB(0, 0, 0));
@@ -44,7 +450,18 @@ fn main() -> Result<(), String> {
         .create_texture_target(None, width, height)
         .map_err(|_| String::from("Unable to create texture."))?;
 
-    let mut game = Game::new(width, height);
+    // The simulation advances in fixed steps whatever the display rate
+    let timestep = Duration::from_millis(33);
+    // Longest time simulating per frame before dropping the backlog
+    let sim_budget = Duration::from_millis(25);
+    let vsync = canvas.info().flags & SDL_RendererFlags::SDL_RENDERER_PRESENTVSYNC as u32 != 0;
+
+    let mut previous = Instant::now();
+    let mut accumulator = Duration::from_secs(0);
+    let mut fps_timer = Instant::now();
+    let mut frames = 0;
+    let mut fps = 0;
+    let mut screenshot = false;
 
     while running {
         for event in event_pump.poll_iter() {
@@ -56,24 +473,108 @@ fn main() -> Result<(), String> {
                 } => {
                     running = false;
                 }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Space),
+                    repeat: false,
+                    ..
+                } => {
+                    if let Mode::Play(play) = &mut mode {
+                        play.flap();
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    ..
+                } => {
+                    screenshot = true;
+                }
+                Event::KeyDown {
+                    keycode: Some(key), ..
+                } => {
+                    if let Mode::Train(game) = &mut mode {
+                        match key {
+                            Keycode::Equals | Keycode::Plus | Keycode::KpPlus => {
+                                game.set_cycle_speed(game.cycle_speed() + 1)
+                            }
+                            Keycode::Minus | Keycode::KpMinus => {
+                                game.set_cycle_speed(game.cycle_speed() - 1)
+                            }
+                            Keycode::C => game.set_coloring(game.coloring().next()),
+                            Keycode::R => game.set_show_rays(!game.show_rays()),
+                            _ => {}
+                        }
+                    }
+                }
                 _ => {}
             }
         }
 
-        //let ticks = timer.ticks() as i32;
+        let now = Instant::now();
+        // Don't try to catch up after long pauses, like dragging the window
+        accumulator += (now - previous).min(Duration::from_millis(250));
+        previous = now;
+
+        'simulate: while accumulator >= timestep {
+            for _ in 0..mode.steps_per_tick() {
+                mode.update();
+                if now.elapsed() > sim_budget {
+                    // Too slow to keep up, stay responsive instead
+                    accumulator = Duration::from_secs(0);
+                    break 'simulate;
+                }
+            }
+            accumulator -= timestep;
+        }
+        let alpha = accumulator.as_secs_f32() / timestep.as_secs_f32();
 
         canvas.clear();
         canvas.set_draw_color(sdl2::pixels::Color::RGB(255, 255, 255));
         canvas
             .with_texture_canvas(&mut tex, |the_canvas| {
-                game.draw(the_canvas).unwrap();
+                mode.draw(the_canvas, &theme, alpha).unwrap();
+                if screenshot {
+                    screenshot = false;
+                    // RGBA bytes in memory, whatever the endianness
+                    let format = if cfg!(target_endian = "little") {
+                        PixelFormatEnum::ABGR8888
+                    } else {
+                        PixelFormatEnum::RGBA8888
+                    };
+                    let path = format!("screenshot-{}.png", timestamp());
+                    let saved = the_canvas
+                        .read_pixels(None, format)
+                        .and_then(|pixels| png::save(&path, width, height, &pixels));
+                    match saved {
+                        Ok(()) => println!("Saved {}", path),
+                        Err(e) => println!("Screenshot failed: {}", e),
+                    }
+                }
             })
             .map_err(|_| String::from("Failed to draw on texture"))?;
         canvas.copy(&tex, None, Rect::new(0, 0, width, height))?;
+
+        frames += 1;
+        if fps_timer.elapsed() >= Duration::from_secs(1) {
+            fps = frames;
+            frames = 0;
+            fps_timer = Instant::now();
+        }
+        canvas.set_draw_color(sdl2::pixels::Color::RGB(0, 255, 0));
+        let mut overlay = format!("FPS {}", fps);
+        if let Mode::Train(game) = &mode {
+            overlay = format!("SPEED X{} {}", game.cycle_speed(), overlay);
+        }
+        let overlay_x = width as i32 - 8 * overlay.len() as i32 - 6;
+        Renderer::text(&mut canvas, overlay_x, 6, 2, &overlay)?;
         canvas.set_draw_color(sdl2::pixels::Color::RGB(0, 0, 0));
         canvas.present();
 
-        std::thread::sleep(Duration::from_millis(33));
+        if !vsync {
+            // Without vsync present() returns right away, wait for the next step
+            if let Some(rest) = timestep.checked_sub(now.elapsed() + accumulator) {
+                std::thread::sleep(rest);
+            }
+        }
     }
 
     Ok(())
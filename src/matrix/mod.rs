@@ -85,8 +85,8 @@ pub mod matrix {
                 }
             }
             Matrix {
-                rows: self.rows,
-                cols: self.cols,
+                rows: self.cols,
+                cols: self.rows,
                 data: new_data,
             }
         }
@@ -105,13 +105,13 @@ pub mod matrix {
             }
             let mut new_data = vec![0.0; (self.rows * _rhs.cols) as usize];
             for i in 0..self.rows {
-                for j in 0..self.cols {
-                    let v = self.data[((i * self.cols) + j) as usize];
+                for j in 0.._rhs.cols {
                     let mut acc = 0.0;
-                    for k in 0.._rhs.rows {
-                        acc += v * _rhs.data[k as usize];
+                    for k in 0..self.cols {
+                        acc += self.data[((i * self.cols) + k) as usize]
+                            * _rhs.data[((k * _rhs.cols) + j) as usize];
                     }
-                    new_data[i as usize] = acc;
+                    new_data[((i * _rhs.cols) + j) as usize] = acc;
                 }
             }
             let new_matrix = Matrix {
@@ -166,5 +166,53 @@ pub mod matrix {
                 data: self.data.iter().map(|x| x - _rhs).collect(),
             }
         }
+
+        // Element by element, not the matrix product
+        pub fn mul_m(&self, _rhs: &Matrix) -> Result<Matrix, String> {
+            if self.rows != _rhs.rows || self.cols != _rhs.cols {
+                return Err("Columns and Rows of A must match Columns and Rows of B".to_string());
+            }
+            Ok(Matrix {
+                cols: self.cols,
+                rows: self.rows,
+                data: self
+                    .data
+                    .iter()
+                    .zip(&_rhs.data)
+                    .map(|(a, b)| a * b)
+                    .collect(),
+            })
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn matrix(rows: i32, cols: i32, data: &[f32]) -> Matrix {
+            Matrix {
+                rows,
+                cols,
+                data: data.to_vec(),
+            }
+        }
+
+        #[test]
+        fn multiplies_rows_by_columns() {
+            let a = matrix(2, 3, &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+            let b = matrix(3, 2, &[7.0, 8.0, 9.0, 10.0, 11.0, 12.0]);
+            let product = a.cross_product(&b).unwrap();
+            assert_eq!((product.rows, product.cols), (2, 2));
+            assert_eq!(product.data, vec![58.0, 64.0, 139.0, 154.0]);
+            assert!(b.cross_product(&b).is_err());
+        }
+
+        #[test]
+        fn transposes_rows_and_columns() {
+            let a = matrix(2, 3, &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+            let t = a.transpose();
+            assert_eq!((t.rows, t.cols), (3, 2));
+            assert_eq!(t.data, vec![1.0, 4.0, 2.0, 5.0, 3.0, 6.0]);
+        }
     }
 }
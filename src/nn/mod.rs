@@ -4,21 +4,82 @@
 pub mod nn {
     use crate::matrix::matrix::Matrix;
     use std::f64::consts::E;
+    use std::fs;
+
+    const BRAIN_HEADER: &str = "rusty-birds brain v2";
+    // Brains from before the matrix product was fixed
+    const OLD_BRAIN_HEADER: &str = "rusty-birds brain v1";
 
     #[derive(Debug, Clone, Copy)]
     pub struct ActivationFunction {
+        // What brain files call it
+        pub name: &'static str,
         pub func: fn(f32) -> f32,
         pub dfunc: fn(f32) -> f32,
     }
 
-    const SIGMOID: ActivationFunction = ActivationFunction {
-        func: |x| 1.0 / (1.0 + E.powf(x.into())) as f32,
+    pub const SIGMOID: ActivationFunction = ActivationFunction {
+        name: "sigmoid",
+        func: |x| 1.0 / (1.0 + E.powf(-x as f64)) as f32,
         dfunc: |y| y * (1.0 - y),
     };
-    const TANH: ActivationFunction = ActivationFunction {
+    pub const TANH: ActivationFunction = ActivationFunction {
+        name: "tanh",
         func: |x| x.tanh(),
         dfunc: |y| 1.0 - (y * y),
     };
+    // Outputs that can be any number, like values
+    pub const LINEAR: ActivationFunction = ActivationFunction {
+        name: "linear",
+        func: |x| x,
+        dfunc: |y| 1.0,
+    };
+
+    fn activation(name: &str) -> Option<ActivationFunction> {
+        [SIGMOID, TANH, LINEAR]
+            .iter()
+            .cloned()
+            .find(|f| f.name == name)
+    }
+
+    /**
+     * How much a loss changes with every weight and bias,
+     * shaped like the network they belong to
+     */
+    #[derive(Debug, Clone)]
+    pub struct Gradients {
+        pub weights_ih: Matrix,
+        pub weights_ho: Matrix,
+        pub bias_h: Matrix,
+        pub bias_o: Matrix,
+    }
+
+    impl Gradients {
+        pub fn zero(nn: &NeuralNetwork) -> Gradients {
+            Gradients {
+                weights_ih: Matrix::new(nn.hidden_nodes, nn.input_nodes),
+                weights_ho: Matrix::new(nn.output_nodes, nn.hidden_nodes),
+                bias_h: Matrix::new(nn.hidden_nodes, 1),
+                bias_o: Matrix::new(nn.output_nodes, 1),
+            }
+        }
+
+        // Sums gradients of several samples
+        pub fn add(&mut self, other: &Gradients) -> Result<(), String> {
+            self.weights_ih = self.weights_ih.add_m(&other.weights_ih)?;
+            self.weights_ho = self.weights_ho.add_m(&other.weights_ho)?;
+            self.bias_h = self.bias_h.add_m(&other.bias_h)?;
+            self.bias_o = self.bias_o.add_m(&other.bias_o)?;
+            Ok(())
+        }
+
+        pub fn scale(&mut self, factor: f32) {
+            self.weights_ih = self.weights_ih.mul(factor);
+            self.weights_ho = self.weights_ho.mul(factor);
+            self.bias_h = self.bias_h.mul(factor);
+            self.bias_o = self.bias_o.mul(factor);
+        }
+    }
 
     #[derive(Debug, Clone)]
     pub struct NeuralNetwork {
@@ -31,6 +92,8 @@ pub mod nn {
         pub bias_o: Matrix,
         learning_rate: f32,
         activation_function: ActivationFunction,
+        // Of the output layer only
+        output_function: ActivationFunction,
     }
 
     impl NeuralNetwork {
@@ -48,6 +111,7 @@ pub mod nn {
 
                 learning_rate: 0.1,
                 activation_function: SIGMOID,
+                output_function: SIGMOID,
             }
         }
 
@@ -63,8 +127,9 @@ pub mod nn {
                 bias_h: nn.bias_h.clone(),
                 bias_o: nn.bias_o.clone(),
 
-                learning_rate: 0.1,
-                activation_function: SIGMOID,
+                learning_rate: nn.learning_rate,
+                activation_function: nn.activation_function,
+                output_function: nn.output_function,
             }
         }
 
@@ -78,19 +143,169 @@ pub mod nn {
 
         pub fn set_activation_function(&mut self, func: ActivationFunction) {
             self.activation_function = func;
+            self.output_function = func;
+        }
+
+        // Saved brains don't keep it, it doesn't change which output is highest
+        pub fn set_output_function(&mut self, func: ActivationFunction) {
+            self.output_function = func;
         }
 
         pub fn predict(&self, input_array: &[f32]) -> Result<Vec<f32>, String> {
+            let (_, output) = self.feed_forward(&Matrix::from_array(input_array))?;
+            Ok(output.to_array())
+        }
+
+        // Hidden and output activations
+        fn feed_forward(&self, inputs: &Matrix) -> Result<(Matrix, Matrix), String> {
             // Generating the Hidden Outputs
-            let inputs = Matrix::from_array(input_array);
-            let mut hidden = self.weights_ih.cross_product(&inputs)?;
+            let mut hidden = self.weights_ih.cross_product(inputs)?;
             hidden = hidden.add_m(&self.bias_h)?;
             hidden = hidden.map(|x, i, j| (self.activation_function.func)(x));
             // Generating the output's output!
             let mut output = self.weights_ho.cross_product(&hidden)?;
             output = output.add_m(&self.bias_o)?;
-            output = output.map(|x, i, j| (self.activation_function.func)(x));
-            Ok(output.to_array())
+            output = output.map(|x, i, j| (self.output_function.func)(x));
+            Ok((hidden, output))
+        }
+
+        /**
+         * Backpropagation: the gradient of a loss for these inputs,
+         * given how much the loss changes with every output
+         */
+        pub fn gradients(
+            &self,
+            input_array: &[f32],
+            output_errors: &[f32],
+        ) -> Result<Gradients, String> {
+            let inputs = Matrix::from_array(input_array);
+            let (hidden, output) = self.feed_forward(&inputs)?;
+            let dfunc = self.activation_function.dfunc;
+            let output_dfunc = self.output_function.dfunc;
+            let output_deltas =
+                Matrix::from_array(output_errors).mul_m(&output.map(|y, i, j| output_dfunc(y)))?;
+            let hidden_errors = self.weights_ho.transpose().cross_product(&output_deltas)?;
+            let hidden_deltas = hidden_errors.mul_m(&hidden.map(|y, i, j| dfunc(y)))?;
+            Ok(Gradients {
+                weights_ih: hidden_deltas.cross_product(&inputs.transpose())?,
+                weights_ho: output_deltas.cross_product(&hidden.transpose())?,
+                bias_h: hidden_deltas,
+                bias_o: output_deltas,
+            })
+        }
+
+        // One step of gradient descent, as big as the learning rate
+        pub fn descend(&mut self, gradients: &Gradients) -> Result<(), String> {
+            let rate = self.learning_rate;
+            self.weights_ih = self.weights_ih.sub_m(&gradients.weights_ih.mul(rate))?;
+            self.weights_ho = self.weights_ho.sub_m(&gradients.weights_ho.mul(rate))?;
+            self.bias_h = self.bias_h.sub_m(&gradients.bias_h.mul(rate))?;
+            self.bias_o = self.bias_o.sub_m(&gradients.bias_o.mul(rate))?;
+            Ok(())
+        }
+
+        // Every weight and bias in one list
+        pub fn genome(&self) -> Vec<f32> {
+            let mut genome = self.weights_ih.data.clone();
+            genome.extend_from_slice(&self.weights_ho.data);
+            genome.extend_from_slice(&self.bias_h.data);
+            genome.extend_from_slice(&self.bias_o.data);
+            genome
+        }
+
+        // Plain text, one line per matrix, so brains can be diffed and checked in
+        pub fn save(&self, path: &str) -> Result<(), String> {
+            let row = |m: &Matrix| {
+                let values: Vec<String> = m.data.iter().map(|x| x.to_string()).collect();
+                values.join(" ")
+            };
+            let text = format!(
+                "{}\nsize {} {} {}\nactivation {} {}\nweights_ih {}\nweights_ho {}\nbias_h {}\nbias_o {}\n",
+                BRAIN_HEADER,
+                self.input_nodes,
+                self.hidden_nodes,
+                self.output_nodes,
+                self.activation_function.name,
+                self.output_function.name,
+                row(&self.weights_ih),
+                row(&self.weights_ho),
+                row(&self.bias_h),
+                row(&self.bias_o)
+            );
+            fs::write(path, text).map_err(|e| format!("Unable to write {}: {}", path, e))
+        }
+
+        pub fn load(path: &str) -> Result<NeuralNetwork, String> {
+            let text =
+                fs::read_to_string(path).map_err(|e| format!("Unable to read {}: {}", path, e))?;
+            let mut lines = text.lines();
+            match lines.next() {
+                Some(BRAIN_HEADER) => {}
+                Some(OLD_BRAIN_HEADER) => {
+                    return Err(format!(
+                        "{} was saved when the network math was broken and won't fly the same, train it again",
+                        path
+                    ))
+                }
+                _ => return Err(format!("{} is not a brain", path)),
+            }
+            let bad = |line: &str| format!("Malformed line in {}: {}", path, line);
+
+            let mut nn: Option<NeuralNetwork> = None;
+            let mut matrices = 0;
+            for line in lines {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                let (name, values) = match fields.split_first() {
+                    Some((name, values)) => (*name, values),
+                    None => continue,
+                };
+                if name == "size" {
+                    let sizes: Vec<i32> = values
+                        .iter()
+                        .map(|v| v.parse().map_err(|_| bad(line)))
+                        .collect::<Result<_, _>>()?;
+                    match sizes.as_slice() {
+                        [i, h, o] if *i > 0 && *h > 0 && *o > 0 => {
+                            nn = Some(NeuralNetwork::new(*i, *h, *o))
+                        }
+                        _ => return Err(bad(line)),
+                    }
+                    continue;
+                }
+                // Weights only make sense once the size is known
+                let nn = nn.as_mut().ok_or_else(|| bad(line))?;
+                // Brains saved without it are sigmoid all the way
+                if name == "activation" {
+                    match values {
+                        [hidden, output] => {
+                            nn.activation_function = activation(hidden).ok_or_else(|| bad(line))?;
+                            nn.output_function = activation(output).ok_or_else(|| bad(line))?;
+                        }
+                        _ => return Err(bad(line)),
+                    }
+                    continue;
+                }
+                let matrix = match name {
+                    "weights_ih" => &mut nn.weights_ih,
+                    "weights_ho" => &mut nn.weights_ho,
+                    "bias_h" => &mut nn.bias_h,
+                    "bias_o" => &mut nn.bias_o,
+                    _ => return Err(bad(line)),
+                };
+                let data: Vec<f32> = values
+                    .iter()
+                    .map(|v| v.parse().map_err(|_| bad(line)))
+                    .collect::<Result<_, _>>()?;
+                if data.len() != matrix.data.len() {
+                    return Err(bad(line));
+                }
+                matrix.data = data;
+                matrices += 1;
+            }
+            match nn {
+                Some(nn) if matrices == 4 => Ok(nn),
+                _ => Err(format!("{} is missing weights", path)),
+            }
         }
 
         pub fn mutate<F>(&mut self, func: F)
@@ -103,4 +318,93 @@ pub mod nn {
             self.bias_o = self.bias_o.map(|x, i, j| func(x));
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn saves_and_loads_weights() {
+            let nn = NeuralNetwork::new(5, 8, 2);
+            let path = std::env::temp_dir().join("rusty-birds-test.brain");
+            let path = path.to_str().unwrap();
+            nn.save(path).unwrap();
+            let loaded = NeuralNetwork::load(path).unwrap();
+            fs::remove_file(path).unwrap();
+            assert_eq!(loaded.genome(), nn.genome());
+            assert_eq!(loaded.hidden_nodes, 8);
+        }
+
+        #[test]
+        fn saves_and_loads_activation_functions() {
+            let mut nn = NeuralNetwork::new(5, 8, 2);
+            nn.set_activation_function(TANH);
+            nn.set_output_function(LINEAR);
+            let path = std::env::temp_dir().join("rusty-birds-test-linear.brain");
+            let path = path.to_str().unwrap();
+            nn.save(path).unwrap();
+            let loaded = NeuralNetwork::load(path).unwrap();
+            fs::remove_file(path).unwrap();
+            let inputs = [3.0, -2.0, 1.0, 0.5, 4.0];
+            assert_eq!(loaded.predict(&inputs), nn.predict(&inputs));
+            assert_eq!(loaded.activation_function.name, "tanh");
+            assert_eq!(loaded.output_function.name, "linear");
+        }
+
+        #[test]
+        fn rejects_brains_from_before_the_fix() {
+            let nn = NeuralNetwork::new(5, 8, 2);
+            let path = std::env::temp_dir().join("rusty-birds-test-v1.brain");
+            let path = path.to_str().unwrap();
+            nn.save(path).unwrap();
+            let text = fs::read_to_string(path).unwrap();
+            fs::write(path, text.replacen(BRAIN_HEADER, OLD_BRAIN_HEADER, 1)).unwrap();
+            let loaded = NeuralNetwork::load(path);
+            fs::remove_file(path).unwrap();
+            assert!(loaded.unwrap_err().contains("train it again"));
+        }
+
+        #[test]
+        fn gradients_match_finite_differences() {
+            let nn = NeuralNetwork::new(3, 4, 2);
+            let inputs = [0.3, -0.7, 0.5];
+            let target = [1.0, 0.0];
+            // Half the squared error, so the output errors are output - target
+            let loss = |nn: &NeuralNetwork| {
+                let output = nn.predict(&inputs).unwrap();
+                let squared: f32 = output
+                    .iter()
+                    .zip(&target)
+                    .map(|(y, t)| (y - t).powi(2))
+                    .sum();
+                squared / 2.0
+            };
+            let output = nn.predict(&inputs).unwrap();
+            let errors: Vec<f32> = output.iter().zip(&target).map(|(y, t)| y - t).collect();
+            let gradients = nn.gradients(&inputs, &errors).unwrap();
+
+            let h = 1e-3;
+            for (i, &analytic) in gradients.weights_ih.data.iter().enumerate() {
+                let (mut up, mut down) = (nn.clone(), nn.clone());
+                up.weights_ih.data[i] += h;
+                down.weights_ih.data[i] -= h;
+                let numeric = (loss(&up) - loss(&down)) / (2.0 * h);
+                assert!(
+                    (numeric - analytic).abs() < 1e-3,
+                    "{} {}",
+                    numeric,
+                    analytic
+                );
+            }
+            let mut up = nn.clone();
+            up.bias_o.data[1] += h;
+            let numeric = (loss(&up) - loss(&nn)) / h;
+            assert!((numeric - gradients.bias_o.data[1]).abs() < 1e-3);
+
+            // Descending makes the loss smaller
+            let mut trained = nn.clone();
+            trained.descend(&gradients).unwrap();
+            assert!(loss(&trained) < loss(&nn));
+        }
+    }
 }
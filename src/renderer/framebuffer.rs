@@ -0,0 +1,175 @@
+use crate::renderer::target::DrawTarget;
+use sdl2::pixels::Color;
+use sdl2::rect::{Point, Rect};
+
+/**
+ * In-memory RGBA image, drawing works the same as on a window
+ * but needs no display at all
+ */
+#[derive(Debug, Clone)]
+pub struct Framebuffer {
+    width: u32,
+    height: u32,
+    // 4 bytes per pixel, row after row
+    pixels: Vec<u8>,
+    color: Color,
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32) -> Framebuffer {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![0; (width * height * 4) as usize],
+            color: Color::RGB(255, 255, 255),
+        }
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    // Blends color over the pixel, anything outside is clipped
+    fn put(&mut self, x: i32, y: i32, color: Color) {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return;
+        }
+        let i = ((y as u32 * self.width + x as u32) * 4) as usize;
+        let a = color.a as u32;
+        let p = &mut self.pixels[i..i + 4];
+        for (dst, src) in p.iter_mut().zip([color.r, color.g, color.b].iter()) {
+            *dst = ((*src as u32 * a + *dst as u32 * (255 - a)) / 255) as u8;
+        }
+        p[3] = (a + p[3] as u32 * (255 - a) / 255) as u8;
+    }
+}
+
+impl DrawTarget for Framebuffer {
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn color(&self) -> Color {
+        self.color
+    }
+
+    fn set_color(&mut self, color: Color) {
+        self.color = color;
+    }
+
+    fn clear(&mut self) -> Result<(), String> {
+        let c = self.color;
+        for p in self.pixels.chunks_mut(4) {
+            p.copy_from_slice(&[c.r, c.g, c.b, c.a]);
+        }
+        Ok(())
+    }
+
+    fn fill_rects(&mut self, rects: &[Rect]) -> Result<(), String> {
+        let color = self.color;
+        for rect in rects.iter() {
+            let top = rect.y().max(0);
+            let bottom = rect.bottom().min(self.height as i32);
+            let left = rect.x().max(0);
+            let right = rect.right().min(self.width as i32);
+            for y in top..bottom {
+                for x in left..right {
+                    self.put(x, y, color);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn draw_points(&mut self, points: &[Point]) -> Result<(), String> {
+        let color = self.color;
+        for point in points.iter() {
+            self.put(point.x(), point.y(), color);
+        }
+        Ok(())
+    }
+
+    fn blend_point(&mut self, x: i32, y: i32, coverage: f32) -> Result<(), String> {
+        let c = self.color;
+        let alpha = (c.a as f32 * coverage.clamp(0.0, 1.0)) as u8;
+        self.put(x, y, Color::RGBA(c.r, c.g, c.b, alpha));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flappy::pipe::{Pipe, PipeConfig, Variant};
+    use crate::renderer::sprite::Sprite;
+
+    fn pixel(frame: &Framebuffer, x: u32, y: u32) -> Color {
+        let i = ((y * frame.width + x) * 4) as usize;
+        let p = &frame.pixels[i..i + 4];
+        Color::RGBA(p[0], p[1], p[2], p[3])
+    }
+
+    #[test]
+    fn draws_a_pipe_without_a_display() {
+        let mut frame = Framebuffer::new(200, 400);
+        frame.set_color(Color::RGB(0, 0, 0));
+        frame.clear().unwrap();
+        frame.set_color(Color::RGB(255, 255, 255));
+        let mut pipe = Pipe::shaped(200, 400, 200.0, &PipeConfig::default(), Variant::Static);
+        pipe.x = 50.0;
+        pipe.prev_x = 50.0;
+        pipe.show(&mut frame, 1.0);
+
+        // Top half, gap and bottom half of the pipe
+        assert_eq!(pixel(&frame, 60, 0), Color::RGB(255, 255, 255));
+        assert_eq!(pixel(&frame, 60, 200), Color::RGB(0, 0, 0));
+        assert_eq!(pixel(&frame, 60, 399), Color::RGB(255, 255, 255));
+        // Left of the pipe
+        assert_eq!(pixel(&frame, 10, 0), Color::RGB(0, 0, 0));
+    }
+
+    #[test]
+    fn blends_partial_coverage() {
+        let mut frame = Framebuffer::new(4, 4);
+        frame.set_color(Color::RGB(0, 0, 0));
+        frame.clear().unwrap();
+        frame.set_color(Color::RGB(255, 255, 255));
+        frame.blend_point(1, 1, 0.5).unwrap();
+        let p = pixel(&frame, 1, 1);
+        assert!(p.r > 100 && p.r < 150);
+        // Off the edge is clipped rather than a panic
+        frame.blend_point(-1, 7, 1.0).unwrap();
+    }
+
+    #[test]
+    fn draws_a_rotated_sprite() {
+        let mut frame = Framebuffer::new(20, 20);
+        frame.set_color(Color::RGB(0, 0, 0));
+        frame.clear().unwrap();
+        // Left half red, right half blue
+        let mut pixels = Vec::new();
+        for x in 0..8 {
+            pixels.extend_from_slice(if x % 4 < 2 {
+                &[255, 0, 0, 255]
+            } else {
+                &[0, 0, 255, 255]
+            });
+        }
+        let sprite = Sprite::new(4, 2, pixels);
+        frame
+            .sprite(
+                &sprite,
+                Rect::new(6, 8, 8, 4),
+                90.0,
+                false,
+                Color::RGB(255, 255, 255),
+            )
+            .unwrap();
+
+        // A quarter turn clockwise puts the left half on top
+        assert_eq!(pixel(&frame, 10, 7), Color::RGB(255, 0, 0));
+        assert_eq!(pixel(&frame, 10, 12), Color::RGB(0, 0, 255));
+        // The unrotated sprite would have covered this
+        assert_eq!(pixel(&frame, 7, 10), Color::RGB(0, 0, 0));
+    }
+}
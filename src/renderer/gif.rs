@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+use std::fs;
+
+/**
+ * Animated GIF writer. Frames are mapped onto a fixed palette
+ * of 6 levels per channel, which keeps the game's colors intact
+ */
+pub struct GifWriter {
+    width: u16,
+    height: u16,
+    // Time each frame stays on screen, in hundredths of a second
+    delay: u16,
+    bytes: Vec<u8>,
+}
+
+// Palette index of the closest color in the 6x6x6 cube
+fn palette_index(r: u8, g: u8, b: u8) -> u8 {
+    let level = |c: u8| (c as u32 * 5 + 127) / 255;
+    (level(r) * 36 + level(g) * 6 + level(b)) as u8
+}
+
+// Variable width codes packed from the lowest bit up
+struct CodeWriter {
+    bytes: Vec<u8>,
+    current: u32,
+    used: u32,
+}
+
+impl CodeWriter {
+    fn write(&mut self, code: u16, size: u32) {
+        self.current |= (code as u32) << self.used;
+        self.used += size;
+        while self.used >= 8 {
+            self.bytes.push(self.current as u8);
+            self.current >>= 8;
+            self.used -= 8;
+        }
+    }
+}
+
+fn lzw(indices: &[u8]) -> Vec<u8> {
+    let clear: u16 = 256;
+    let end: u16 = 257;
+    let mut out = CodeWriter {
+        bytes: Vec::new(),
+        current: 0,
+        used: 0,
+    };
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = end + 1;
+    let mut size = 9;
+    out.write(clear, size);
+
+    let mut prefix: Option<u16> = None;
+    for &index in indices.iter() {
+        prefix = match prefix {
+            None => Some(index as u16),
+            Some(p) => match table.get(&(p, index)) {
+                Some(&code) => Some(code),
+                None => {
+                    out.write(p, size);
+                    if next == 4096 {
+                        // Table is full, start over
+                        out.write(clear, size);
+                        table.clear();
+                        next = end + 1;
+                        size = 9;
+                    } else {
+                        table.insert((p, index), next);
+                        if next == 1 << size {
+                            size += 1;
+                        }
+                        next += 1;
+                    }
+                    Some(index as u16)
+                }
+            },
+        };
+    }
+    if let Some(p) = prefix {
+        out.write(p, size);
+    }
+    out.write(end, size);
+    if out.used > 0 {
+        out.bytes.push(out.current as u8);
+    }
+    out.bytes
+}
+
+impl GifWriter {
+    pub fn new(width: u32, height: u32, delay: u16) -> Result<GifWriter, String> {
+        if width == 0 || height == 0 || width > u16::MAX as u32 || height > u16::MAX as u32 {
+            return Err(format!("Can't write a {}x{} GIF", width, height));
+        }
+        let mut bytes = b"GIF89a".to_vec();
+        bytes.extend_from_slice(&(width as u16).to_le_bytes());
+        bytes.extend_from_slice(&(height as u16).to_le_bytes());
+        // Global palette of 256 colors, background 0, square pixels
+        bytes.extend_from_slice(&[0xf7, 0, 0]);
+        for i in 0..256u32 {
+            let (r, g, b) = if i < 216 {
+                (i / 36, i / 6 % 6, i % 6)
+            } else {
+                (0, 0, 0)
+            };
+            bytes.extend_from_slice(&[(r * 51) as u8, (g * 51) as u8, (b * 51) as u8]);
+        }
+        // Loop forever
+        bytes.extend_from_slice(&[0x21, 0xff, 0x0b]);
+        bytes.extend_from_slice(b"NETSCAPE2.0");
+        bytes.extend_from_slice(&[0x03, 0x01, 0x00, 0x00, 0x00]);
+        Ok(GifWriter {
+            width: width as u16,
+            height: height as u16,
+            delay,
+            bytes,
+        })
+    }
+
+    pub fn add_frame(&mut self, rgba: &[u8]) {
+        let indices: Vec<u8> = rgba
+            .chunks(4)
+            .map(|p| palette_index(p[0], p[1], p[2]))
+            .collect();
+        // Graphic control extension with the frame delay
+        self.bytes.extend_from_slice(&[0x21, 0xf9, 0x04, 0x00]);
+        self.bytes.extend_from_slice(&self.delay.to_le_bytes());
+        self.bytes.extend_from_slice(&[0x00, 0x00]);
+        // Image descriptor covering the whole screen
+        self.bytes.push(0x2c);
+        self.bytes.extend_from_slice(&[0, 0, 0, 0]);
+        self.bytes.extend_from_slice(&self.width.to_le_bytes());
+        self.bytes.extend_from_slice(&self.height.to_le_bytes());
+        self.bytes.push(0x00);
+        // Minimum code size, then the data in blocks of up to 255 bytes
+        self.bytes.push(8);
+        for block in lzw(&indices).chunks(255) {
+            self.bytes.push(block.len() as u8);
+            self.bytes.extend_from_slice(block);
+        }
+        self.bytes.push(0x00);
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        self.bytes.push(0x3b);
+        self.bytes
+    }
+
+    pub fn save(self, path: &str) -> Result<(), String> {
+        fs::write(path, self.finish()).map_err(|e| format!("Unable to write {}: {}", path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reads codes the way GIF decoders do, growing the code size once the table fills it
+    fn unlzw(bytes: &[u8]) -> Vec<u8> {
+        let (clear, end) = (256, 257);
+        let mut table: Vec<Vec<u8>> = (0..=255).map(|i| vec![i]).collect();
+        table.extend([Vec::new(), Vec::new()]);
+        let (mut bit, mut size) = (0, 9);
+        let mut prev: Option<usize> = None;
+        let mut out = Vec::new();
+        loop {
+            let code = (0..size).fold(0, |code, i| {
+                let b = (bytes[(bit + i) / 8] >> ((bit + i) % 8)) & 1;
+                code | (b as usize) << i
+            });
+            bit += size;
+            if code == clear {
+                table.truncate(258);
+                size = 9;
+                prev = None;
+                continue;
+            }
+            if code == end {
+                return out;
+            }
+            let entry = match table.get(code) {
+                Some(entry) => entry.clone(),
+                // The code being defined right now, previous plus its own first byte
+                None => {
+                    let mut entry = table[prev.unwrap()].clone();
+                    entry.push(entry[0]);
+                    entry
+                }
+            };
+            if let Some(prev) = prev {
+                let mut added = table[prev].clone();
+                added.push(entry[0]);
+                table.push(added);
+            }
+            out.extend_from_slice(&entry);
+            if table.len() == 1 << size && size < 12 {
+                size += 1;
+            }
+            prev = Some(code);
+        }
+    }
+
+    #[test]
+    fn lzw_round_trips() {
+        let repeated = vec![7u8; 5000];
+        assert_eq!(unlzw(&lzw(&repeated)), repeated);
+        // Enough different runs to fill the table and start over a few times
+        let mut x: u32 = 1;
+        let noisy: Vec<u8> = (0..40_000)
+            .map(|_| {
+                x = x.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (x >> 16) as u8 % 40
+            })
+            .collect();
+        assert_eq!(unlzw(&lzw(&noisy)), noisy);
+        assert_eq!(unlzw(&lzw(&[])), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn rejects_empty_images() {
+        assert!(GifWriter::new(0, 10, 3).is_err());
+        assert!(GifWriter::new(10, 0, 3).is_err());
+        let mut gif = GifWriter::new(1, 1, 3).unwrap();
+        gif.add_frame(&[255, 0, 0, 255]);
+        let bytes = gif.finish();
+        assert_eq!(&bytes[..6], b"GIF89a");
+        assert_eq!(bytes.last(), Some(&0x3b));
+    }
+}
@@ -1,37 +1,124 @@
+pub mod framebuffer;
+pub mod gif;
+pub mod png;
+pub mod sprite;
+pub mod svg;
+pub mod target;
+
 pub mod renderer {
+    use crate::renderer::sprite::Sprite;
+    use crate::renderer::target::DrawTarget;
+    use sdl2::pixels::Color;
     use sdl2::rect::{Point, Rect};
-    use sdl2::render::Canvas;
-    use sdl2::video::Window;
+
+    /**
+     * Rasterizes shapes with the few primitives every DrawTarget
+     * provides, so all backends draw them the same way
+     */
     pub struct Renderer {}
 
+    // 3x5 bitmap glyphs, one byte per row, the 3 lowest bits are the pixels
+    fn glyph(c: char) -> [u8; 5] {
+        match c.to_ascii_uppercase() {
+            '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+            '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+            '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+            '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+            '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+            '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+            '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+            '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+            '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+            '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+            'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+            'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+            'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+            'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+            'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+            'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+            'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+            'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+            'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+            'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+            'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+            'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+            'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+            'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+            'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+            'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+            'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+            'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+            'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+            'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+            'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+            'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+            'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+            'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+            'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+            'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+            ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+            '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+            '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+            '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+            _ => [0; 5],
+        }
+    }
+
     impl Renderer {
-        pub fn rect(
-            canvas: &mut Canvas<Window>,
+        pub fn rect<T: DrawTarget + ?Sized>(
+            canvas: &mut T,
             x: i32,
             y: i32,
             width: u32,
             height: u32,
         ) -> Result<(), String> {
-            canvas.fill_rect(Rect::new(x, y, width, height))
+            canvas.fill_rects(&[Rect::new(x, y, width, height)])
+        }
+
+        /**
+         * Draws text with the built-in 3x5 pixel font, every font
+         * pixel becomes a square of `scale` pixels
+         */
+        pub fn text<T: DrawTarget + ?Sized>(
+            canvas: &mut T,
+            x: i32,
+            y: i32,
+            scale: u32,
+            text: &str,
+        ) -> Result<(), String> {
+            let s = scale as i32;
+            let mut rects = Vec::new();
+            for (n, c) in text.chars().enumerate() {
+                let left = x + n as i32 * 4 * s;
+                for (row, bits) in glyph(c).iter().enumerate() {
+                    for col in 0..3 {
+                        if bits & (0b100 >> col) != 0 {
+                            rects.push(Rect::new(left + col * s, y + row as i32 * s, scale, scale));
+                        }
+                    }
+                }
+            }
+            canvas.fill_rects(&rects)
         }
 
-        pub fn draw_circle(
-            canvas: &mut Canvas<Window>,
+        pub fn draw_circle<T: DrawTarget + ?Sized>(
+            canvas: &mut T,
             center: Point,
             radius: i32,
         ) -> Result<(), String> {
+            let mut points = Vec::new();
             let mut x = radius;
             let mut y = 0;
             let mut re = x * x + y * y - radius * radius;
             while x >= y {
-                canvas.draw_point(Point::new(center.x() + x, center.y() + y))?;
-                canvas.draw_point(Point::new(center.x() + y, center.y() + x))?;
-                canvas.draw_point(Point::new(center.x() - x, center.y() + y))?;
-                canvas.draw_point(Point::new(center.x() - y, center.y() + x))?;
-                canvas.draw_point(Point::new(center.x() - x, center.y() - y))?;
-                canvas.draw_point(Point::new(center.x() - y, center.y() - x))?;
-                canvas.draw_point(Point::new(center.x() + x, center.y() - y))?;
-                canvas.draw_point(Point::new(center.x() + y, center.y() - x))?;
+                points.push(Point::new(center.x() + x, center.y() + y));
+                points.push(Point::new(center.x() + y, center.y() + x));
+                points.push(Point::new(center.x() - x, center.y() + y));
+                points.push(Point::new(center.x() - y, center.y() + x));
+                points.push(Point::new(center.x() - x, center.y() - y));
+                points.push(Point::new(center.x() - y, center.y() - x));
+                points.push(Point::new(center.x() + x, center.y() - y));
+                points.push(Point::new(center.x() + y, center.y() - x));
                 if 2 * (re + 2 * y + 1) + 1 - 2 * x > 0 {
                     re += 1 - 2 * x;
                     x -= 1;
@@ -39,6 +126,262 @@ pub mod renderer {
                 re += 2 * y + 1;
                 y += 1;
             }
+            canvas.draw_points(points.as_slice())
+        }
+
+        // One rect per row of the circle
+        fn circle_spans(center: Point, radius: i32, spans: &mut Vec<Rect>) {
+            for dy in -radius..=radius {
+                let half = ((radius * radius - dy * dy) as f32).sqrt() as i32;
+                spans.push(Rect::new(
+                    center.x() - half,
+                    center.y() + dy,
+                    2 * half as u32 + 1,
+                    1,
+                ));
+            }
+        }
+
+        pub fn fill_circle<T: DrawTarget + ?Sized>(
+            canvas: &mut T,
+            center: Point,
+            radius: i32,
+        ) -> Result<(), String> {
+            Renderer::fill_circles(canvas, &[(center, radius)])
+        }
+
+        /**
+         * Fills all the circles with a single draw call, this is
+         * what keeps hundreds of birds cheap to draw
+         */
+        pub fn fill_circles<T: DrawTarget + ?Sized>(
+            canvas: &mut T,
+            circles: &[(Point, i32)],
+        ) -> Result<(), String> {
+            let mut spans = Vec::new();
+            for &(center, radius) in circles.iter() {
+                Renderer::circle_spans(center, radius, &mut spans);
+            }
+            canvas.fill_rects(&spans)
+        }
+
+        // Draws with the current color made transparent by coverage (0 to 1)
+        fn blend_points<T: DrawTarget + ?Sized>(
+            canvas: &mut T,
+            points: &[(i32, i32, f32)],
+        ) -> Result<(), String> {
+            for &(x, y, coverage) in points.iter() {
+                canvas.blend_point(x, y, coverage)?;
+            }
+            Ok(())
+        }
+
+        // Xiaolin Wu's anti-aliased line
+        pub fn draw_line_aa<T: DrawTarget + ?Sized>(
+            canvas: &mut T,
+            from: (f32, f32),
+            to: (f32, f32),
+        ) -> Result<(), String> {
+            let (mut x0, mut y0, mut x1, mut y1) = (from.0, from.1, to.0, to.1);
+            // Walk along the longest axis
+            let steep = (y1 - y0).abs() > (x1 - x0).abs();
+            if steep {
+                std::mem::swap(&mut x0, &mut y0);
+                std::mem::swap(&mut x1, &mut y1);
+            }
+            if x0 > x1 {
+                std::mem::swap(&mut x0, &mut x1);
+                std::mem::swap(&mut y0, &mut y1);
+            }
+            let dx = x1 - x0;
+            let gradient = if dx == 0.0 { 1.0 } else { (y1 - y0) / dx };
+
+            let mut points = Vec::new();
+            let mut y = y0 + gradient * (x0.round() - x0);
+            for x in x0.round() as i32..=x1.round() as i32 {
+                let floor = y.floor();
+                let frac = y - floor;
+                let (a, b) = (floor as i32, floor as i32 + 1);
+                if steep {
+                    points.push((a, x, 1.0 - frac));
+                    points.push((b, x, frac));
+                } else {
+                    points.push((x, a, 1.0 - frac));
+                    points.push((x, b, frac));
+                }
+                y += gradient;
+            }
+            Renderer::blend_points(canvas, &points)
+        }
+
+        // Anti-aliased circle outline, Wu's algorithm on every octant
+        pub fn draw_circle_aa<T: DrawTarget + ?Sized>(
+            canvas: &mut T,
+            center: (f32, f32),
+            radius: f32,
+        ) -> Result<(), String> {
+            let (cx, cy) = center;
+            let mut points = Vec::new();
+            let last = (radius / std::f32::consts::SQRT_2).ceil() as i32;
+            for x in 0..=last {
+                let y = (radius * radius - (x * x) as f32).max(0.0).sqrt();
+                let floor = y.floor();
+                let frac = y - floor;
+                for &(dy, coverage) in [(floor, 1.0 - frac), (floor + 1.0, frac)].iter() {
+                    let (fx, fy) = (x as f32, dy);
+                    for &(px, py) in [(fx, fy), (fy, fx)].iter() {
+                        for &(sx, sy) in [(1.0, 1.0), (-1.0, 1.0), (1.0, -1.0), (-1.0, -1.0)].iter()
+                        {
+                            let point_x = (cx + sx * px).round() as i32;
+                            let point_y = (cy + sy * py).round() as i32;
+                            points.push((point_x, point_y, coverage));
+                        }
+                    }
+                }
+            }
+            Renderer::blend_points(canvas, &points)
+        }
+
+        /**
+         * Filled circle with anti-aliased edges: every row is filled
+         * solid and the partly covered pixel at each end is blended
+         */
+        pub fn fill_circle_aa<T: DrawTarget + ?Sized>(
+            canvas: &mut T,
+            center: (f32, f32),
+            radius: f32,
+        ) -> Result<(), String> {
+            let (cx, cy) = center;
+            let mut spans = Vec::new();
+            let mut edges = Vec::new();
+            for row in (cy - radius).floor() as i32..=(cy + radius).ceil() as i32 {
+                let dy = row as f32 + 0.5 - cy;
+                if dy.abs() >= radius {
+                    continue;
+                }
+                let half = (radius * radius - dy * dy).sqrt();
+                let (left, right) = (cx - half, cx + half);
+                let (inner_left, inner_right) = (left.ceil() as i32, right.floor() as i32);
+                if inner_right > inner_left {
+                    let width = (inner_right - inner_left) as u32;
+                    spans.push(Rect::new(inner_left, row, width, 1));
+                }
+                edges.push((inner_left - 1, row, inner_left as f32 - left));
+                edges.push((inner_right, row, right - inner_right as f32));
+            }
+            canvas.fill_rects(&spans)?;
+            Renderer::blend_points(canvas, &edges)
+        }
+
+        // Filled rect with its corners rounded by radius
+        pub fn rounded_rect<T: DrawTarget + ?Sized>(
+            canvas: &mut T,
+            x: i32,
+            y: i32,
+            width: u32,
+            height: u32,
+            radius: u32,
+        ) -> Result<(), String> {
+            let r = radius.min(width / 2).min(height / 2) as i32;
+            let mut rects = Vec::new();
+            if height as i32 > 2 * r {
+                rects.push(Rect::new(x, y + r, width, height - 2 * r as u32));
+            }
+            for row in 0..r {
+                // Distance from the row to the center of the corner circles
+                let dy = r as f32 - row as f32 - 0.5;
+                let inset = r - ((r * r) as f32 - dy * dy).sqrt().round() as i32;
+                let row_width = (width as i32 - 2 * inset).max(0) as u32;
+                rects.push(Rect::new(x + inset, y + row, row_width, 1));
+                rects.push(Rect::new(
+                    x + inset,
+                    y + height as i32 - 1 - row,
+                    row_width,
+                    1,
+                ));
+            }
+            canvas.fill_rects(&rects)
+        }
+
+        // Scanline fill with the even-odd rule
+        pub fn fill_polygon<T: DrawTarget + ?Sized>(
+            canvas: &mut T,
+            points: &[Point],
+        ) -> Result<(), String> {
+            if points.len() < 3 {
+                return Ok(());
+            }
+            let min_y = points.iter().map(|p| p.y()).min().unwrap();
+            let max_y = points.iter().map(|p| p.y()).max().unwrap();
+            let mut spans = Vec::new();
+            for row in min_y..max_y {
+                let scan = row as f32 + 0.5;
+                let mut crossings = Vec::new();
+                for i in 0..points.len() {
+                    let a = points[i];
+                    let b = points[(i + 1) % points.len()];
+                    let (ay, by) = (a.y() as f32, b.y() as f32);
+                    if (ay <= scan) != (by <= scan) {
+                        let t = (scan - ay) / (by - ay);
+                        crossings.push(a.x() as f32 + t * (b.x() - a.x()) as f32);
+                    }
+                }
+                crossings.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                for pair in crossings.chunks(2) {
+                    if let [from, to] = pair {
+                        let left = from.round() as i32;
+                        let width = (to.round() as i32 - left).max(0) as u32;
+                        if width > 0 {
+                            spans.push(Rect::new(left, row, width, 1));
+                        }
+                    }
+                }
+            }
+            canvas.fill_rects(&spans)
+        }
+
+        // Sprite scaled into dst and rotated clockwise by angle degrees
+        // around its center, every pixel is looked up backwards
+        pub fn sprite<T: DrawTarget + ?Sized>(
+            canvas: &mut T,
+            sprite: &Sprite,
+            dst: Rect,
+            angle: f32,
+            flip_vertical: bool,
+            tint: Color,
+        ) -> Result<(), String> {
+            let color = canvas.color();
+            let modulate = |c: u8, t: u8| (c as u32 * t as u32 / 255) as u8;
+            let (sin, cos) = angle.to_radians().sin_cos();
+            let (w, h) = (dst.width() as f32, dst.height() as f32);
+            let (cx, cy) = (dst.x() as f32 + w / 2.0, dst.y() as f32 + h / 2.0);
+            let reach = (w * w + h * h).sqrt() / 2.0;
+            for y in (cy - reach) as i32..=(cy + reach) as i32 {
+                for x in (cx - reach) as i32..=(cx + reach) as i32 {
+                    let (dx, dy) = (x as f32 + 0.5 - cx, y as f32 + 0.5 - cy);
+                    let u = (dx * cos + dy * sin) / w + 0.5;
+                    let mut v = (dy * cos - dx * sin) / h + 0.5;
+                    if !(0.0..1.0).contains(&u) || !(0.0..1.0).contains(&v) {
+                        continue;
+                    }
+                    if flip_vertical {
+                        v = 1.0 - v;
+                    }
+                    let sx = ((u * sprite.width() as f32) as u32).min(sprite.width() - 1);
+                    let sy = ((v * sprite.height() as f32) as u32).min(sprite.height() - 1);
+                    let pixel = sprite.pixel(sx, sy);
+                    if pixel.a > 0 {
+                        canvas.set_color(Color::RGBA(
+                            modulate(pixel.r, tint.r),
+                            modulate(pixel.g, tint.g),
+                            modulate(pixel.b, tint.b),
+                            pixel.a,
+                        ));
+                        canvas.blend_point(x, y, 1.0)?;
+                    }
+                }
+            }
+            canvas.set_color(color);
             Ok(())
         }
     }
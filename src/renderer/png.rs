@@ -0,0 +1,257 @@
+use std::fs;
+
+/*
+ * Minimal PNG encoder for RGBA images. Image data is compressed
+ * with deflate using the fixed Huffman codes, which is plenty for
+ * the flat colors of the game
+ */
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+const WINDOW: usize = 32768;
+const MAX_MATCH: usize = 258;
+
+// Deflate streams are filled starting from the lowest bit of each byte
+struct BitWriter {
+    bytes: Vec<u8>,
+    current: u32,
+    used: u32,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter {
+            bytes: Vec::new(),
+            current: 0,
+            used: 0,
+        }
+    }
+
+    fn bits(&mut self, value: u32, count: u32) {
+        for i in 0..count {
+            self.current |= ((value >> i) & 1) << self.used;
+            self.used += 1;
+            if self.used == 8 {
+                self.bytes.push(self.current as u8);
+                self.current = 0;
+                self.used = 0;
+            }
+        }
+    }
+
+    // Huffman codes go most significant bit first
+    fn code(&mut self, code: u32, length: u32) {
+        for i in (0..length).rev() {
+            self.bits((code >> i) & 1, 1);
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.used > 0 {
+            self.bytes.push(self.current as u8);
+        }
+        self.bytes
+    }
+}
+
+fn literal(out: &mut BitWriter, symbol: u32) {
+    match symbol {
+        0..=143 => out.code(0x30 + symbol, 8),
+        144..=255 => out.code(0x190 + symbol - 144, 9),
+        256..=279 => out.code(symbol - 256, 7),
+        _ => out.code(0xC0 + symbol - 280, 8),
+    }
+}
+
+fn length_and_distance(out: &mut BitWriter, length: usize, distance: usize) {
+    let l = LENGTH_BASE
+        .iter()
+        .rposition(|&b| b as usize <= length)
+        .unwrap();
+    literal(out, 257 + l as u32);
+    out.bits(
+        (length - LENGTH_BASE[l] as usize) as u32,
+        LENGTH_EXTRA[l] as u32,
+    );
+    let d = DISTANCE_BASE
+        .iter()
+        .rposition(|&b| b as usize <= distance)
+        .unwrap();
+    out.code(d as u32, 5);
+    out.bits(
+        (distance - DISTANCE_BASE[d] as usize) as u32,
+        DISTANCE_EXTRA[d] as u32,
+    );
+}
+
+// A single fixed Huffman block, greedy matching on 3 byte hashes
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut out = BitWriter::new();
+    // Final block, fixed codes
+    out.bits(1, 1);
+    out.bits(1, 2);
+
+    let mut last_seen = vec![usize::MAX; 1 << 15];
+    let hash = |i: usize| {
+        ((data[i] as usize) << 10 ^ (data[i + 1] as usize) << 5 ^ data[i + 2] as usize) & 0x7fff
+    };
+    let mut i = 0;
+    while i < data.len() {
+        let mut best = 0;
+        let mut distance = 0;
+        if i + 3 <= data.len() {
+            let h = hash(i);
+            let candidate = last_seen[h];
+            last_seen[h] = i;
+            if candidate != usize::MAX && i - candidate <= WINDOW {
+                let max = MAX_MATCH.min(data.len() - i);
+                let mut length = 0;
+                while length < max && data[candidate + length] == data[i + length] {
+                    length += 1;
+                }
+                if length >= 3 {
+                    best = length;
+                    distance = i - candidate;
+                }
+            }
+        }
+        if best >= 3 {
+            length_and_distance(&mut out, best, distance);
+            // Keep the hashes of the skipped bytes for later matches
+            for j in i + 1..(i + best).min(data.len().saturating_sub(2)) {
+                last_seen[hash(j)] = j;
+            }
+            i += best;
+        } else {
+            literal(&mut out, data[i] as u32);
+            i += 1;
+        }
+    }
+    literal(&mut out, 256);
+    out.finish()
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk.iter() {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &byte in data.iter() {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+// PNG file for width x height pixels, 4 RGBA bytes each
+pub fn encode(width: u32, height: u32, rgba: &[u8]) -> Result<Vec<u8>, String> {
+    if width == 0 || height == 0 {
+        return Err(format!("Can't encode a {}x{} image", width, height));
+    }
+    if rgba.len() != (width * height * 4) as usize {
+        return Err(format!(
+            "{} bytes don't make a {}x{} image",
+            rgba.len(),
+            width,
+            height
+        ));
+    }
+    // Every row starts with its filter type, 0 is none
+    let mut raw = Vec::with_capacity(rgba.len() + height as usize);
+    for row in rgba.chunks((width * 4) as usize) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+    let mut zlib = vec![0x78, 0x01];
+    zlib.extend(deflate(&raw));
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // 8 bits per channel, RGBA, default compression, filter and no interlace
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+    let mut png = vec![0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+    chunk(&mut png, b"IHDR", &header);
+    chunk(&mut png, b"IDAT", &zlib);
+    chunk(&mut png, b"IEND", &[]);
+    Ok(png)
+}
+
+pub fn save(path: &str, width: u32, height: u32, rgba: &[u8]) -> Result<(), String> {
+    fs::write(path, encode(width, height, rgba)?)
+        .map_err(|e| format!("Unable to write {}: {}", path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums_match_known_values() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+        assert_eq!(adler32(b""), 1);
+    }
+
+    // Both checked against zlib's inflate
+    #[test]
+    fn encodes_known_bytes() {
+        assert_eq!(
+            deflate(b"flappy flappy flappy bird"),
+            vec![75, 203, 73, 44, 40, 168, 84, 64, 165, 146, 50, 139, 82, 0]
+        );
+        let red = encode(1, 1, &[255, 0, 0, 255]).unwrap();
+        let expected: Vec<u8> = vec![
+            137, 80, 78, 71, 13, 10, 26, 10, 0, 0, 0, 13, 73, 72, 68, 82, 0, 0, 0, 1, 0, 0, 0, 1,
+            8, 6, 0, 0, 0, 31, 21, 196, 137, 0, 0, 0, 13, 73, 68, 65, 84, 120, 1, 99, 248, 207,
+            192, 240, 31, 0, 5, 0, 1, 255, 123, 233, 190, 239, 0, 0, 0, 0, 73, 69, 78, 68, 174, 66,
+            96, 130,
+        ];
+        assert_eq!(red, expected);
+    }
+
+    #[test]
+    fn rejects_empty_images() {
+        assert!(encode(0, 10, &[]).is_err());
+        assert!(encode(10, 0, &[]).is_err());
+        assert!(encode(2, 2, &[0; 4]).is_err());
+    }
+}
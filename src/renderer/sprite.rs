@@ -0,0 +1,84 @@
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::surface::Surface;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/**
+ * An RGBA image to draw scaled and rotated. Backends that keep
+ * textures around use the id to upload each sprite only once
+ */
+#[derive(Debug, Clone)]
+pub struct Sprite {
+    id: usize,
+    width: u32,
+    height: u32,
+    // 4 bytes per pixel, row after row
+    pixels: Vec<u8>,
+}
+
+impl Sprite {
+    pub fn new(width: u32, height: u32, pixels: Vec<u8>) -> Sprite {
+        Sprite {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    // PNG needs the image feature, BMP always works
+    pub fn load(path: &str) -> Result<Sprite, String> {
+        let surface = load_surface(path)?.convert_format(PixelFormatEnum::RGBA32)?;
+        let (width, height) = (surface.width(), surface.height());
+        let pitch = surface.pitch() as usize;
+        // Rows may be padded, copy only the pixels
+        let pixels = surface.with_lock(|data| {
+            data.chunks(pitch)
+                .take(height as usize)
+                .flat_map(|row| row[..(width * 4) as usize].iter().cloned())
+                .collect()
+        });
+        Ok(Sprite::new(width, height, pixels))
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> Color {
+        let i = ((y * self.width + x) * 4) as usize;
+        let p = &self.pixels[i..i + 4];
+        Color::RGBA(p[0], p[1], p[2], p[3])
+    }
+}
+
+#[cfg(feature = "image")]
+fn load_surface(path: &str) -> Result<Surface<'static>, String> {
+    use sdl2::image::LoadSurface;
+    Surface::from_file(path).map_err(|e| format!("Unable to load {}: {}", path, e))
+}
+
+#[cfg(not(feature = "image"))]
+fn load_surface(path: &str) -> Result<Surface<'static>, String> {
+    if path.ends_with(".bmp") {
+        Surface::load_bmp(path).map_err(|e| format!("Unable to load {}: {}", path, e))
+    } else {
+        Err(format!(
+            "Unable to load {}: build with --features image for PNG sprites, or use BMP",
+            path
+        ))
+    }
+}
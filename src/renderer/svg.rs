@@ -0,0 +1,188 @@
+use crate::renderer::target::DrawTarget;
+use sdl2::pixels::Color;
+use sdl2::rect::{Point, Rect};
+use std::fs;
+
+/**
+ * Writes drawings as an SVG document, shapes stay vectors
+ * instead of being rasterized into pixels
+ */
+#[derive(Debug, Clone)]
+pub struct SvgWriter {
+    width: u32,
+    height: u32,
+    color: Color,
+    elements: Vec<String>,
+}
+
+impl SvgWriter {
+    pub fn new(width: u32, height: u32) -> SvgWriter {
+        SvgWriter {
+            width,
+            height,
+            color: Color::RGB(255, 255, 255),
+            elements: Vec::new(),
+        }
+    }
+
+    // fill or stroke attributes for the current color
+    fn paint(&self, attribute: &str, coverage: f32) -> String {
+        let c = self.color;
+        let opacity = c.a as f32 / 255.0 * coverage.clamp(0.0, 1.0);
+        let mut paint = format!("{}=\"rgb({},{},{})\"", attribute, c.r, c.g, c.b);
+        if opacity < 1.0 {
+            paint.push_str(&format!(" {}-opacity=\"{:.3}\"", attribute, opacity));
+        }
+        paint
+    }
+
+    pub fn to_svg(&self) -> String {
+        format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n{}\n</svg>\n",
+            self.elements.join("\n"),
+            w = self.width,
+            h = self.height
+        )
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        fs::write(path, self.to_svg()).map_err(|e| format!("Unable to write {}: {}", path, e))
+    }
+}
+
+impl DrawTarget for SvgWriter {
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn color(&self) -> Color {
+        self.color
+    }
+
+    fn set_color(&mut self, color: Color) {
+        self.color = color;
+    }
+
+    // Everything drawn so far is covered, so start a fresh document
+    fn clear(&mut self) -> Result<(), String> {
+        self.elements.clear();
+        let background = format!(
+            "<rect width=\"100%\" height=\"100%\" {}/>",
+            self.paint("fill", 1.0)
+        );
+        self.elements.push(background);
+        Ok(())
+    }
+
+    fn fill_rects(&mut self, rects: &[Rect]) -> Result<(), String> {
+        let fill = self.paint("fill", 1.0);
+        for r in rects.iter() {
+            self.elements.push(format!(
+                "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" {}/>",
+                r.x(),
+                r.y(),
+                r.width(),
+                r.height(),
+                fill
+            ));
+        }
+        Ok(())
+    }
+
+    fn draw_points(&mut self, points: &[Point]) -> Result<(), String> {
+        let rects: Vec<Rect> = points
+            .iter()
+            .map(|p| Rect::new(p.x(), p.y(), 1, 1))
+            .collect();
+        self.fill_rects(&rects)
+    }
+
+    fn rounded_rect(
+        &mut self,
+        x: i32,
+        y: i32,
+        width: u32,
+        height: u32,
+        radius: u32,
+    ) -> Result<(), String> {
+        let fill = self.paint("fill", 1.0);
+        self.elements.push(format!(
+            "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" rx=\"{}\" {}/>",
+            x, y, width, height, radius, fill
+        ));
+        Ok(())
+    }
+
+    fn blend_point(&mut self, x: i32, y: i32, coverage: f32) -> Result<(), String> {
+        let fill = self.paint("fill", coverage);
+        self.elements.push(format!(
+            "<rect x=\"{}\" y=\"{}\" width=\"1\" height=\"1\" {}/>",
+            x, y, fill
+        ));
+        Ok(())
+    }
+
+    fn line_aa(&mut self, from: (f32, f32), to: (f32, f32)) -> Result<(), String> {
+        let stroke = self.paint("stroke", 1.0);
+        self.elements.push(format!(
+            "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" {}/>",
+            from.0, from.1, to.0, to.1, stroke
+        ));
+        Ok(())
+    }
+
+    fn circle(&mut self, center: Point, radius: i32) -> Result<(), String> {
+        let stroke = self.paint("stroke", 1.0);
+        self.elements.push(format!(
+            "<circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"none\" {}/>",
+            center.x(),
+            center.y(),
+            radius,
+            stroke
+        ));
+        Ok(())
+    }
+
+    fn circle_aa(&mut self, center: (f32, f32), radius: f32) -> Result<(), String> {
+        let stroke = self.paint("stroke", 1.0);
+        self.elements.push(format!(
+            "<circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"none\" {}/>",
+            center.0, center.1, radius, stroke
+        ));
+        Ok(())
+    }
+
+    fn fill_circle(&mut self, center: Point, radius: i32) -> Result<(), String> {
+        self.fill_circle_aa((center.x() as f32, center.y() as f32), radius as f32)
+    }
+
+    fn fill_circles(&mut self, circles: &[(Point, i32)]) -> Result<(), String> {
+        for &(center, radius) in circles.iter() {
+            self.fill_circle(center, radius)?;
+        }
+        Ok(())
+    }
+
+    fn fill_circle_aa(&mut self, center: (f32, f32), radius: f32) -> Result<(), String> {
+        let fill = self.paint("fill", 1.0);
+        self.elements.push(format!(
+            "<circle cx=\"{}\" cy=\"{}\" r=\"{}\" {}/>",
+            center.0, center.1, radius, fill
+        ));
+        Ok(())
+    }
+
+    fn fill_polygon(&mut self, points: &[Point]) -> Result<(), String> {
+        let fill = self.paint("fill", 1.0);
+        let points: Vec<String> = points
+            .iter()
+            .map(|p| format!("{},{}", p.x(), p.y()))
+            .collect();
+        self.elements.push(format!(
+            "<polygon points=\"{}\" {}/>",
+            points.join(" "),
+            fill
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,158 @@
+use crate::renderer::renderer::Renderer;
+use crate::renderer::sprite::Sprite;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::{Point, Rect};
+use sdl2::render::{BlendMode, Canvas, RenderTarget, Texture};
+use std::cell::RefCell;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+/**
+ * Anything the game can be drawn on. A backend only has to provide
+ * the primitives without a default, every other shape is rasterized
+ * by Renderer unless the backend can draw it better itself
+ */
+pub trait DrawTarget {
+    // Width and height in pixels
+    fn size(&self) -> (u32, u32);
+    fn color(&self) -> Color;
+    fn set_color(&mut self, color: Color);
+    // Fill everything with the current color
+    fn clear(&mut self) -> Result<(), String>;
+    fn fill_rects(&mut self, rects: &[Rect]) -> Result<(), String>;
+    fn draw_points(&mut self, points: &[Point]) -> Result<(), String>;
+    // The current color made transparent by coverage (0 to 1)
+    fn blend_point(&mut self, x: i32, y: i32, coverage: f32) -> Result<(), String>;
+
+    fn rect(&mut self, x: i32, y: i32, width: u32, height: u32) -> Result<(), String> {
+        Renderer::rect(self, x, y, width, height)
+    }
+
+    fn rounded_rect(
+        &mut self,
+        x: i32,
+        y: i32,
+        width: u32,
+        height: u32,
+        radius: u32,
+    ) -> Result<(), String> {
+        Renderer::rounded_rect(self, x, y, width, height, radius)
+    }
+
+    fn line_aa(&mut self, from: (f32, f32), to: (f32, f32)) -> Result<(), String> {
+        Renderer::draw_line_aa(self, from, to)
+    }
+
+    fn circle(&mut self, center: Point, radius: i32) -> Result<(), String> {
+        Renderer::draw_circle(self, center, radius)
+    }
+
+    fn circle_aa(&mut self, center: (f32, f32), radius: f32) -> Result<(), String> {
+        Renderer::draw_circle_aa(self, center, radius)
+    }
+
+    fn fill_circle(&mut self, center: Point, radius: i32) -> Result<(), String> {
+        Renderer::fill_circle(self, center, radius)
+    }
+
+    fn fill_circles(&mut self, circles: &[(Point, i32)]) -> Result<(), String> {
+        Renderer::fill_circles(self, circles)
+    }
+
+    fn fill_circle_aa(&mut self, center: (f32, f32), radius: f32) -> Result<(), String> {
+        Renderer::fill_circle_aa(self, center, radius)
+    }
+
+    fn fill_polygon(&mut self, points: &[Point]) -> Result<(), String> {
+        Renderer::fill_polygon(self, points)
+    }
+
+    fn text(&mut self, x: i32, y: i32, scale: u32, text: &str) -> Result<(), String> {
+        Renderer::text(self, x, y, scale, text)
+    }
+
+    // Stretched into dst, then turned clockwise by angle degrees.
+    // Every pixel is multiplied by tint, white leaves it unchanged
+    fn sprite(
+        &mut self,
+        sprite: &Sprite,
+        dst: Rect,
+        angle: f32,
+        flip_vertical: bool,
+        tint: Color,
+    ) -> Result<(), String> {
+        Renderer::sprite(self, sprite, dst, angle, flip_vertical, tint)
+    }
+}
+
+thread_local! {
+    // Sprites already uploaded to the GPU, by sprite id
+    static TEXTURES: RefCell<HashMap<usize, Texture>> = RefCell::new(HashMap::new());
+}
+
+// SDL2 canvases, both windows and texture targets
+impl<T: RenderTarget> DrawTarget for Canvas<T> {
+    fn size(&self) -> (u32, u32) {
+        let viewport = self.viewport();
+        (viewport.width(), viewport.height())
+    }
+
+    fn color(&self) -> Color {
+        self.draw_color()
+    }
+
+    fn set_color(&mut self, color: Color) {
+        self.set_draw_color(color);
+    }
+
+    fn clear(&mut self) -> Result<(), String> {
+        Canvas::clear(self);
+        Ok(())
+    }
+
+    fn fill_rects(&mut self, rects: &[Rect]) -> Result<(), String> {
+        Canvas::fill_rects(self, rects)
+    }
+
+    fn draw_points(&mut self, points: &[Point]) -> Result<(), String> {
+        Canvas::draw_points(self, points)
+    }
+
+    fn blend_point(&mut self, x: i32, y: i32, coverage: f32) -> Result<(), String> {
+        let color = self.draw_color();
+        let blend_mode = self.blend_mode();
+        self.set_blend_mode(BlendMode::Blend);
+        let alpha = (color.a as f32 * coverage.clamp(0.0, 1.0)) as u8;
+        self.set_draw_color(Color::RGBA(color.r, color.g, color.b, alpha));
+        let result = self.draw_point(Point::new(x, y));
+        self.set_draw_color(color);
+        self.set_blend_mode(blend_mode);
+        result
+    }
+
+    fn sprite(
+        &mut self,
+        sprite: &Sprite,
+        dst: Rect,
+        angle: f32,
+        flip_vertical: bool,
+        tint: Color,
+    ) -> Result<(), String> {
+        TEXTURES.with(|textures| {
+            let mut textures = textures.borrow_mut();
+            if let Entry::Vacant(slot) = textures.entry(sprite.id()) {
+                let mut texture = self
+                    .create_texture_static(PixelFormatEnum::RGBA32, sprite.width(), sprite.height())
+                    .map_err(|e| e.to_string())?;
+                texture
+                    .update(None, sprite.pixels(), (sprite.width() * 4) as usize)
+                    .map_err(|e| e.to_string())?;
+                texture.set_blend_mode(BlendMode::Blend);
+                slot.insert(texture);
+            }
+            let texture = textures.get_mut(&sprite.id()).unwrap();
+            texture.set_color_mod(tint.r, tint.g, tint.b);
+            self.copy_ex(texture, None, dst, angle as f64, None, false, flip_vertical)
+        })
+    }
+}